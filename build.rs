@@ -0,0 +1,72 @@
+// 在编译时把src目录下所有的.vert和.frag着色器编译成spirv
+// 编译结果保存在OUT_DIR中, 目录结构和src相同, 文件名后面加上.spv, 例如:
+// src/data/quad.vert -> $OUT_DIR/data/quad.vert.spv
+
+extern crate glsl_to_spirv;
+
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+fn main() {
+    let src_dir = Path::new("src");
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    println!("cargo:rerun-if-changed={}", src_dir.display());
+
+    let mut shaders = Vec::new();
+    find_shaders(src_dir, &mut shaders);
+
+    // 收集所有的错误, 一次性报告
+    let mut errors = Vec::new();
+    for path in shaders {
+        println!("cargo:rerun-if-changed={}", path.display());
+        let shader_type = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("vert") => glsl_to_spirv::ShaderType::Vertex,
+            Some("frag") => glsl_to_spirv::ShaderType::Fragment,
+            _ => unreachable!(),
+        };
+        let glsl = fs::read_to_string(&path)
+            .unwrap_or_else(|err| panic!("Cannot read {}: {}", path.display(), err));
+        match glsl_to_spirv::compile(&glsl, shader_type) {
+            Ok(mut file) => {
+                let mut spirv = Vec::new();
+                file.read_to_end(&mut spirv).unwrap();
+                let relative = path.strip_prefix(src_dir).unwrap();
+                let out_path = out_dir.join(format!("{}.spv", relative.display()));
+                fs::create_dir_all(out_path.parent().unwrap()).unwrap();
+                fs::write(&out_path, spirv).unwrap();
+            }
+            Err(message) => errors.push(locate_errors(&path, &message)),
+        }
+    }
+
+    if !errors.is_empty() {
+        panic!("Cannot compile shaders:\n{}", errors.join("\n"));
+    }
+}
+
+// 递归查找目录下的所有着色器文件
+fn find_shaders(dir: &Path, shaders: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            find_shaders(&path, shaders);
+        } else if let Some("vert") | Some("frag") = path.extension().and_then(|ext| ext.to_str()) {
+            shaders.push(path);
+        }
+    }
+}
+
+// glslang报告的错误位置形如 `ERROR: 0:12: ...`, 其中0是源字符串的编号
+// 把它替换成文件路径, 得到 `ERROR: src/data/quad.frag:12: ...`
+fn locate_errors(path: &Path, message: &str) -> String {
+    message
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| match line.find(" 0:") {
+            Some(pos) => format!("{} {}:{}", &line[..pos], path.display(), &line[pos + 3..]),
+            None => format!("{}: {}", path.display(), line),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,68 @@
+// gfx_test的派生宏
+// #[derive(VertexFormat)]根据结构体的字段生成顶点属性, 见gfx_test::vertex
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Meta, NestedMeta};
+
+// 每个字段对应一个顶点属性, location按照字段的声明顺序从0开始
+// 字段的偏移量由编译器决定, 所以结构体必须是#[repr(C)]的
+#[proc_macro_derive(VertexFormat)]
+pub fn derive_vertex_format(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+
+    if !is_repr_c(&input) {
+        return error(name.span(), "VertexFormat requires #[repr(C)]");
+    }
+    let fields = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => &fields.named,
+            _ => return error(name.span(), "VertexFormat requires a struct with named fields"),
+        },
+        _ => return error(name.span(), "VertexFormat can only be derived for structs"),
+    };
+
+    let attributes = fields.iter().enumerate().map(|(location, field)| {
+        let ident = &field.ident;
+        let ty = &field.ty;
+        let location = location as u32;
+        quote! {
+            ::gfx_test::vertex::attribute::<#ty>(
+                #location,
+                binding,
+                &vertex.#ident as *const _ as usize - base,
+            )
+        }
+    });
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let expanded = quote! {
+        impl #impl_generics ::gfx_test::vertex::VertexFormat for #name #ty_generics #where_clause {
+            fn attributes(binding: u32) -> ::std::vec::Vec<::gfx_test::vertex::AttributeDesc> {
+                // 用一个全零的顶点计算每个字段相对于结构体开头的偏移量
+                let vertex: Self = unsafe { ::std::mem::zeroed() };
+                let base = &vertex as *const Self as usize;
+                vec![#(#attributes),*]
+            }
+        }
+    };
+    expanded.into()
+}
+
+fn is_repr_c(input: &DeriveInput) -> bool {
+    input.attrs.iter().any(|attr| match attr.parse_meta() {
+        Ok(Meta::List(ref list)) if list.ident == "repr" => list.nested.iter().any(|nested| match nested {
+            NestedMeta::Meta(Meta::Word(ref ident)) => ident == "C",
+            _ => false,
+        }),
+        _ => false,
+    })
+}
+
+fn error(span: Span, message: &str) -> TokenStream {
+    syn::Error::new(span, message).to_compile_error().into()
+}
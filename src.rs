@@ -1,5 +1,17 @@
-// extern crate gfx_backend_vulkan as backend;
+// 根据cargo feature选择后端, 默认情况下windows使用dx12, 其他平台使用vulkan
+// empty后端需要手动指定, 指定后优先使用
+#[cfg(feature = "empty")]
+extern crate gfx_backend_empty as backend;
+#[cfg(all(not(feature = "empty"), windows, feature = "dx12"))]
 extern crate gfx_backend_dx12 as backend;
+#[cfg(all(not(feature = "empty"), not(all(windows, feature = "dx12")), feature = "vulkan"))]
+extern crate gfx_backend_vulkan as backend;
+
+#[cfg(not(any(feature = "vulkan", feature = "dx12", feature = "empty")))]
+compile_error!("需要选择一个后端: 请启用 `vulkan`, `dx12` 或 `empty` feature 之一");
+#[cfg(all(not(windows), feature = "dx12", not(any(feature = "vulkan", feature = "empty"))))]
+compile_error!("dx12后端只能在windows上使用, 请改用 `vulkan` 或 `empty` feature");
+
 extern crate gfx_hal as hal;
 extern crate winit;
 extern crate image;
@@ -9,6 +21,7 @@ const ENTRY_NAME: &str = "main";
 
 use hal::Instance;
 use hal::adapter::PhysicalDevice;
+use hal::queue::QueueFamily;
 use hal::window::Surface;
 use hal::device::Device;
 use hal::pso::DescriptorPool;
@@ -41,7 +54,13 @@ const COLOR_RANGE: hal::image::SubresourceRange = hal::image::SubresourceRange {
     layers: 0..1,
 };
 
+// empty后端没有窗口和表面, 无法渲染
+#[cfg(feature = "empty")]
+fn main() {
+    println!("empty后端无法创建表面, 请使用 `vulkan` 或 `dx12` feature 运行");
+}
 
+#[cfg(not(feature = "empty"))]
 fn main() {
     // 首先创建一个物理窗口
     let mut events_loop = winit::EventsLoop::new();
@@ -59,12 +78,48 @@ fn main() {
     let mut surface = instance.create_surface(&window);
     // 创建一组适配器: 适配器表示一个物理设备
     let mut adapters = instance.enumerate_adapters();
-    // 打印适配器的信息, 不知道有什么用
-    for adapter in &adapters {
-        println!("{:?}", adapter.info);
+    // 打印适配器的信息: 名字, 厂商id, 设备id和设备类型, 设备类型用来判断是独立显卡还是集成显卡
+    for (index, adapter) in adapters.iter().enumerate() {
+        println!("{}: {:?}", index, adapter.info);
     }
-    // 然后获取第一个适配器, 我们就用这个适配器来运行程序
-    let mut adapter = adapters.remove(0);
+    // 去掉没有和surface兼容的图形队列族的适配器, 这样的适配器无法向窗口绘制
+    adapters.retain(|adapter| {
+        adapter.queue_families
+            .iter()
+            .any(|family| family.supports_graphics() && surface.supports_queue_family(family))
+    });
+    // 用 `--adapter <名字>` 参数或者GFX_ADAPTER环境变量手动指定适配器, 名字只需要包含其中的一部分
+    let wanted = std::env::args()
+        .skip_while(|arg| arg != "--adapter")
+        .nth(1)
+        .or_else(|| std::env::var("GFX_ADAPTER").ok())
+        .map(|name| name.to_lowercase());
+    let position = match wanted {
+        Some(name) => adapters
+            .iter()
+            .position(|adapter| adapter.info.name.to_lowercase().contains(&name))
+            .unwrap_or_else(|| panic!("No usable adapter matches {}", name)),
+        // 否则按照设备类型选择, 独立显卡最好, CPU模拟的设备最差, 类型相同时比较最大纹理尺寸
+        None => adapters
+            .iter()
+            .enumerate()
+            .max_by_key(|&(index, adapter)| {
+                let rank = match adapter.info.device_type {
+                    hal::adapter::DeviceType::DiscreteGpu => 4,
+                    hal::adapter::DeviceType::IntegratedGpu => 3,
+                    hal::adapter::DeviceType::VirtualGpu => 2,
+                    hal::adapter::DeviceType::Other => 1,
+                    hal::adapter::DeviceType::Cpu => 0,
+                };
+                // 分数相同时使用靠前的适配器
+                (rank, adapter.physical_device.limits().max_texture_size, std::cmp::Reverse(index))
+            })
+            .map(|(index, _)| index)
+            .expect("No usable adapter"),
+    };
+    // 我们就用选中的适配器来运行程序
+    let mut adapter = adapters.remove(position);
+    println!("Using {}", adapter.info.name);
     // 获取显卡的内存类型, 资源限制
     let memory_types = adapter.physical_device.memory_properties().memory_types;
     let limits = adapter.physical_device.limits();
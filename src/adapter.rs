@@ -0,0 +1,130 @@
+// 选择适配器: 先过滤掉没有可用图形队列族的适配器, 再按照设备类型和资源限制排序
+// 笔记本和CI中第一个适配器往往是集成显卡或者软件渲染器, 所以不能直接使用第一个
+// 可以用命令行参数 `--adapter <序号或名字>` 或者环境变量GFX_ADAPTER手动指定,
+// 序号是enumerate_adapters返回的顺序, 名字不区分大小写, 匹配名字中的一部分即可
+
+use hal::adapter::PhysicalDevice;
+
+use crate::Back;
+use crate::error::AppError;
+
+// 指定适配器的环境变量
+pub const ADAPTER_ENV: &str = "GFX_ADAPTER";
+// 指定适配器的命令行参数, 优先于环境变量
+pub const ADAPTER_ARG: &str = "--adapter";
+
+// 手动指定的适配器
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdapterOverride {
+    Index(usize),
+    Name(String),
+}
+
+impl AdapterOverride {
+    // 纯数字是序号, 否则是名字
+    pub fn parse(value: &str) -> Self {
+        match value.trim().parse() {
+            Ok(index) => AdapterOverride::Index(index),
+            Err(_) => AdapterOverride::Name(value.trim().to_lowercase()),
+        }
+    }
+
+    // 从命令行参数和环境变量中读取, 都没有设置时返回None
+    pub fn from_env() -> Option<Self> {
+        let args: Vec<String> = std::env::args().collect();
+        let arg = args
+            .iter()
+            .position(|arg| arg == ADAPTER_ARG)
+            .and_then(|pos| args.get(pos + 1).cloned());
+        arg.or_else(|| std::env::var(ADAPTER_ENV).ok())
+            .filter(|value| !value.trim().is_empty())
+            .map(|value| Self::parse(&value))
+    }
+
+    fn matches(&self, index: usize, info: &hal::AdapterInfo) -> bool {
+        match self {
+            AdapterOverride::Index(i) => *i == index,
+            AdapterOverride::Name(name) => info.name.to_lowercase().contains(name.as_str()),
+        }
+    }
+}
+
+// 适配器的排序依据, 按字段顺序比较, 越大越好
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Score {
+    // 独立显卡 > 集成显卡 > 虚拟显卡 > 其他 > CPU
+    pub device_type: u8,
+    // DEVICE_LOCAL堆的总大小
+    pub device_memory: u64,
+    pub max_texture_size: usize,
+}
+
+impl Score {
+    pub fn new(adapter: &hal::Adapter<Back>) -> Self {
+        let device_type = match adapter.info.device_type {
+            hal::adapter::DeviceType::DiscreteGpu => 4,
+            hal::adapter::DeviceType::IntegratedGpu => 3,
+            hal::adapter::DeviceType::VirtualGpu => 2,
+            hal::adapter::DeviceType::Other => 1,
+            hal::adapter::DeviceType::Cpu => 0,
+        };
+        // 集成显卡的DEVICE_LOCAL堆就是系统内存, 只能在同一种设备类型中比较
+        let memory_properties = adapter.physical_device.memory_properties();
+        let mut device_heaps = vec![false; memory_properties.memory_heaps.len()];
+        for memory_type in &memory_properties.memory_types {
+            if memory_type.properties.contains(hal::memory::Properties::DEVICE_LOCAL) {
+                device_heaps[memory_type.heap_index] = true;
+            }
+        }
+        let device_memory = memory_properties.memory_heaps
+            .iter()
+            .zip(device_heaps)
+            .filter(|&(_, device_local)| device_local)
+            .map(|(size, _)| *size)
+            .sum();
+        Score {
+            device_type,
+            device_memory,
+            max_texture_size: adapter.physical_device.limits().max_texture_size,
+        }
+    }
+}
+
+// 从adapters中选出一个适配器, 选中的适配器的名字和类型见adapter.info
+// supports_family判断队列族是否可用, 例如是否和surface兼容, 只会传入支持图形能力的队列族
+pub fn select_adapter<F>(
+    adapters: Vec<hal::Adapter<Back>>,
+    adapter_override: Option<&AdapterOverride>,
+    supports_family: F,
+) -> Result<hal::Adapter<Back>, AppError>
+where
+    F: Fn(&<Back as hal::Backend>::QueueFamily) -> bool,
+{
+    use hal::queue::QueueFamily;
+
+    let mut candidates = Vec::new();
+    for (index, adapter) in adapters.into_iter().enumerate() {
+        let usable = adapter.queue_families
+            .iter()
+            .any(|family| family.supports_graphics() && supports_family(family));
+        if usable {
+            candidates.push((index, Score::new(&adapter), adapter));
+        }
+    }
+
+    let position = match adapter_override {
+        Some(adapter_override) => candidates
+            .iter()
+            .position(|(index, _, adapter)| adapter_override.matches(*index, &adapter.info))
+            .ok_or_else(|| AppError::AdapterOverride(adapter_override.clone()))?,
+        // 分数相同时使用靠前的适配器
+        None => candidates
+            .iter()
+            .enumerate()
+            .max_by(|(i, (_, a, _)), (j, (_, b, _))| a.cmp(b).then(j.cmp(i)))
+            .map(|(position, _)| position)
+            .ok_or(AppError::NoAdapter)?,
+    };
+    let (_, _, adapter) = candidates.swap_remove(position);
+    Ok(adapter)
+}
@@ -0,0 +1,573 @@
+// 块压缩纹理的容器格式: DDS和KTX2
+// 只读取压缩块数据, 不做任何转换; 数据按mip层级排列, 每个层级中按数组层依次排列
+// 立方体贴图的6个面也算作数组层
+
+use std::fmt;
+
+const DDS_MAGIC: &[u8; 4] = b"DDS ";
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n',
+];
+// KTX1的标识符, 只用来给出更明确的错误
+const KTX1_IDENTIFIER: [u8; 12] = [
+    0xAB, b'K', b'T', b'X', b' ', b'1', b'1', 0xBB, b'\r', b'\n', 0x1A, b'\n',
+];
+
+// DDS文件头中用到的标志
+const DDPF_FOURCC: u32 = 0x4;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_VOLUME: u32 = 0x20_0000;
+const DDS_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+const DDS_DIMENSION_TEXTURE3D: u32 = 4;
+
+// 数组层数的上限, 和常见设备的maxImageArrayLayers相同
+// 文件头中的层数来自不可信的输入, 需要在计算数据大小之前检查
+const MAX_LAYERS: u32 = 2048;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ContainerError {
+    // 既不是DDS也不是KTX2
+    UnknownContainer,
+    // 文件在需要的数据之前就结束了
+    Truncated,
+    // 不支持的像素格式, 内容是文件中格式的描述
+    UnsupportedFormat(String),
+    // 不支持的特性, 例如3D纹理和超压缩
+    Unsupported(&'static str),
+    // 文件头中的数值不合理, 例如层级数超过了尺寸允许的最大值
+    InvalidHeader(&'static str),
+}
+
+impl fmt::Display for ContainerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ContainerError::UnknownContainer => write!(f, "not a DDS or KTX2 file"),
+            ContainerError::Truncated => write!(f, "file is truncated"),
+            ContainerError::UnsupportedFormat(format) => write!(f, "unsupported format {}", format),
+            ContainerError::Unsupported(what) => write!(f, "{} are not supported", what),
+            ContainerError::InvalidHeader(what) => write!(f, "invalid header: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for ContainerError {}
+
+// 支持的块压缩格式, 每个块都是4x4像素
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockFormat {
+    // 不透明的BC1, 3色模式中的第4种颜色是黑色
+    Bc1Rgb,
+    // 带1位alpha的BC1, 3色模式中的第4种颜色是透明的黑色
+    Bc1Rgba,
+    Bc2,
+    Bc3,
+    Bc4,
+    Bc5,
+    Bc6h,
+    Bc7,
+    Etc2Rgb,
+    Etc2RgbA1,
+    Etc2Rgba,
+}
+
+impl BlockFormat {
+    // 每个块的字节数
+    pub fn block_size(self) -> u32 {
+        match self {
+            BlockFormat::Bc1Rgb
+            | BlockFormat::Bc1Rgba
+            | BlockFormat::Bc4
+            | BlockFormat::Etc2Rgb
+            | BlockFormat::Etc2RgbA1 => 8,
+            BlockFormat::Bc2
+            | BlockFormat::Bc3
+            | BlockFormat::Bc5
+            | BlockFormat::Bc6h
+            | BlockFormat::Bc7
+            | BlockFormat::Etc2Rgba => 16,
+        }
+    }
+
+    // 对应的hal格式, BC4, BC5和BC6H没有sRGB版本
+    pub fn format(self, srgb: bool) -> hal::format::Format {
+        use hal::format::Format;
+        match (self, srgb) {
+            (BlockFormat::Bc1Rgb, false) => Format::Bc1RgbUnorm,
+            (BlockFormat::Bc1Rgb, true) => Format::Bc1RgbSrgb,
+            (BlockFormat::Bc1Rgba, false) => Format::Bc1RgbaUnorm,
+            (BlockFormat::Bc1Rgba, true) => Format::Bc1RgbaSrgb,
+            (BlockFormat::Bc2, false) => Format::Bc2Unorm,
+            (BlockFormat::Bc2, true) => Format::Bc2Srgb,
+            (BlockFormat::Bc3, false) => Format::Bc3Unorm,
+            (BlockFormat::Bc3, true) => Format::Bc3Srgb,
+            (BlockFormat::Bc4, _) => Format::Bc4Unorm,
+            (BlockFormat::Bc5, _) => Format::Bc5Unorm,
+            (BlockFormat::Bc6h, _) => Format::Bc6hUfloat,
+            (BlockFormat::Bc7, false) => Format::Bc7Unorm,
+            (BlockFormat::Bc7, true) => Format::Bc7Srgb,
+            (BlockFormat::Etc2Rgb, false) => Format::Etc2R8g8b8Unorm,
+            (BlockFormat::Etc2Rgb, true) => Format::Etc2R8g8b8Srgb,
+            (BlockFormat::Etc2RgbA1, false) => Format::Etc2R8g8b8a1Unorm,
+            (BlockFormat::Etc2RgbA1, true) => Format::Etc2R8g8b8a1Srgb,
+            (BlockFormat::Etc2Rgba, false) => Format::Etc2R8g8b8a8Unorm,
+            (BlockFormat::Etc2Rgba, true) => Format::Etc2R8g8b8a8Srgb,
+        }
+    }
+}
+
+pub struct CompressedImage {
+    pub format: BlockFormat,
+    pub srgb: bool,
+    pub width: u32,
+    pub height: u32,
+    pub layers: hal::image::Layer,
+    // 每个mip层级的数据, 第0层是原始尺寸
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl CompressedImage {
+    // 按文件头判断容器格式并读取
+    pub fn parse(bytes: &[u8]) -> Result<Self, ContainerError> {
+        if bytes.starts_with(DDS_MAGIC) {
+            parse_dds(bytes)
+        } else if bytes.starts_with(&KTX2_IDENTIFIER) {
+            parse_ktx2(bytes)
+        } else if bytes.starts_with(&KTX1_IDENTIFIER) {
+            Err(ContainerError::Unsupported("KTX1 files"))
+        } else {
+            Err(ContainerError::UnknownContainer)
+        }
+    }
+
+    // 文件头是否是支持的容器格式
+    pub fn is_container(bytes: &[u8]) -> bool {
+        bytes.starts_with(DDS_MAGIC) || bytes.starts_with(&KTX2_IDENTIFIER)
+    }
+
+    // 第level层的像素尺寸
+    pub fn level_extent(&self, level: usize) -> (u32, u32) {
+        ((self.width >> level).max(1), (self.height >> level).max(1))
+    }
+
+    // 第level层中一个数组层的字节数, parse已经确认文件中有这么多数据, 不会溢出
+    pub fn layer_size(&self, level: usize) -> usize {
+        let (width, height) = self.level_extent(level);
+        layer_size(self.format, width, height).unwrap()
+    }
+}
+
+// 一个数组层的字节数, 超出usize时返回None
+fn layer_size(format: BlockFormat, width: u32, height: u32) -> Option<usize> {
+    let blocks_wide = (width as usize + 3) / 4;
+    let blocks_high = (height as usize + 3) / 4;
+    blocks_wide
+        .checked_mul(blocks_high)?
+        .checked_mul(format.block_size() as usize)
+}
+
+// 第level层所有数组层的字节数, 太大时返回错误, 这样的文件不可能包含这么多数据
+fn level_size(
+    format: BlockFormat,
+    width: u32,
+    height: u32,
+    level: usize,
+    layers: u32,
+) -> Result<usize, ContainerError> {
+    layer_size(format, (width >> level).max(1), (height >> level).max(1))
+        .and_then(|size| size.checked_mul(layers as usize))
+        .ok_or(ContainerError::Truncated)
+}
+
+// 在读取数据之前检查文件头中的尺寸, 层级数和数组层数
+// 最小的层级是1x1, 所以层级数不能超过32 - max(width, height).leading_zeros()
+fn check_header(width: u32, height: u32, level_count: u32, layers: u32) -> Result<(), ContainerError> {
+    if width == 0 || height == 0 {
+        return Err(ContainerError::Unsupported("empty textures"));
+    }
+    if level_count > 32 - width.max(height).leading_zeros() {
+        return Err(ContainerError::InvalidHeader("more mip levels than the size allows"));
+    }
+    if layers > MAX_LAYERS {
+        return Err(ContainerError::InvalidHeader("too many array layers"));
+    }
+    Ok(())
+}
+
+fn slice(bytes: &[u8], offset: usize, len: usize) -> Result<&[u8], ContainerError> {
+    offset
+        .checked_add(len)
+        .and_then(|end| bytes.get(offset..end))
+        .ok_or(ContainerError::Truncated)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, ContainerError> {
+    let mut value = [0u8; 4];
+    value.copy_from_slice(slice(bytes, offset, 4)?);
+    Ok(u32::from_le_bytes(value))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64, ContainerError> {
+    let mut value = [0u8; 8];
+    value.copy_from_slice(slice(bytes, offset, 8)?);
+    Ok(u64::from_le_bytes(value))
+}
+
+// DDS: 4字节的标识, 124字节的文件头, fourCC为DX10时还有20字节的扩展头
+// 数据按数组层排列, 每一层包含所有mip层级
+fn parse_dds(bytes: &[u8]) -> Result<CompressedImage, ContainerError> {
+    let height = read_u32(bytes, 12)?;
+    let width = read_u32(bytes, 16)?;
+    let level_count = read_u32(bytes, 28)?.max(1);
+    let pixel_flags = read_u32(bytes, 80)?;
+    let mut four_cc = [0u8; 4];
+    four_cc.copy_from_slice(slice(bytes, 84, 4)?);
+    let caps2 = read_u32(bytes, 112)?;
+    if caps2 & DDSCAPS2_VOLUME != 0 {
+        return Err(ContainerError::Unsupported("3D textures"));
+    }
+    if pixel_flags & DDPF_FOURCC == 0 {
+        return Err(ContainerError::UnsupportedFormat("uncompressed DDS".to_string()));
+    }
+
+    let (format, srgb, layers, data_offset) = if &four_cc == b"DX10" {
+        let dxgi_format = read_u32(bytes, 128)?;
+        let dimension = read_u32(bytes, 132)?;
+        let misc_flags = read_u32(bytes, 136)?;
+        let array_size = read_u32(bytes, 140)?.max(1);
+        if dimension == DDS_DIMENSION_TEXTURE3D {
+            return Err(ContainerError::Unsupported("3D textures"));
+        }
+        let (format, srgb) = match dxgi_format {
+            71 => (BlockFormat::Bc1Rgba, false),
+            72 => (BlockFormat::Bc1Rgba, true),
+            74 => (BlockFormat::Bc2, false),
+            75 => (BlockFormat::Bc2, true),
+            77 => (BlockFormat::Bc3, false),
+            78 => (BlockFormat::Bc3, true),
+            80 => (BlockFormat::Bc4, false),
+            83 => (BlockFormat::Bc5, false),
+            95 => (BlockFormat::Bc6h, false),
+            98 => (BlockFormat::Bc7, false),
+            99 => (BlockFormat::Bc7, true),
+            other => {
+                return Err(ContainerError::UnsupportedFormat(format!("DXGI format {}", other)));
+            }
+        };
+        let faces = if misc_flags & DDS_RESOURCE_MISC_TEXTURECUBE != 0 { 6 } else { 1 };
+        (format, srgb, array_size.saturating_mul(faces), 148)
+    } else {
+        let format = match &four_cc {
+            b"DXT1" => BlockFormat::Bc1Rgba,
+            b"DXT2" | b"DXT3" => BlockFormat::Bc2,
+            b"DXT4" | b"DXT5" => BlockFormat::Bc3,
+            b"ATI1" | b"BC4U" => BlockFormat::Bc4,
+            b"ATI2" | b"BC5U" => BlockFormat::Bc5,
+            _ => {
+                return Err(ContainerError::UnsupportedFormat(format!(
+                    "DDS fourCC {}",
+                    String::from_utf8_lossy(&four_cc),
+                )));
+            }
+        };
+        let faces = if caps2 & DDSCAPS2_CUBEMAP != 0 { 6 } else { 1 };
+        (format, false, faces, 128)
+    };
+    check_header(width, height, level_count, layers)?;
+
+    let mut levels = vec![Vec::new(); level_count as usize];
+    let mut offset = data_offset;
+    for _ in 0..layers {
+        for (level, data) in levels.iter_mut().enumerate() {
+            let size = level_size(format, width, height, level, 1)?;
+            data.extend_from_slice(slice(bytes, offset, size)?);
+            offset += size;
+        }
+    }
+    Ok(CompressedImage {
+        format,
+        srgb,
+        width,
+        height,
+        layers: layers as hal::image::Layer,
+        levels,
+    })
+}
+
+// KTX2: 12字节的标识, 68字节的文件头和索引, 之后是每个层级的偏移和长度
+// 每个层级中按数组层, 立方体面的顺序排列
+fn parse_ktx2(bytes: &[u8]) -> Result<CompressedImage, ContainerError> {
+    let vk_format = read_u32(bytes, 12)?;
+    let width = read_u32(bytes, 20)?;
+    let height = read_u32(bytes, 24)?.max(1);
+    let depth = read_u32(bytes, 28)?;
+    let layer_count = read_u32(bytes, 32)?.max(1);
+    let face_count = read_u32(bytes, 36)?.max(1);
+    let level_count = read_u32(bytes, 40)?.max(1);
+    let supercompression = read_u32(bytes, 44)?;
+    if depth > 1 {
+        return Err(ContainerError::Unsupported("3D textures"));
+    }
+    if supercompression != 0 {
+        return Err(ContainerError::Unsupported("supercompressed KTX2 files"));
+    }
+    if face_count != 1 && face_count != 6 {
+        return Err(ContainerError::InvalidHeader("face count must be 1 or 6"));
+    }
+    // 数值和VkFormat相同
+    let (format, srgb) = match vk_format {
+        131 => (BlockFormat::Bc1Rgb, false),
+        132 => (BlockFormat::Bc1Rgb, true),
+        133 => (BlockFormat::Bc1Rgba, false),
+        134 => (BlockFormat::Bc1Rgba, true),
+        135 => (BlockFormat::Bc2, false),
+        136 => (BlockFormat::Bc2, true),
+        137 => (BlockFormat::Bc3, false),
+        138 => (BlockFormat::Bc3, true),
+        139 => (BlockFormat::Bc4, false),
+        141 => (BlockFormat::Bc5, false),
+        143 => (BlockFormat::Bc6h, false),
+        145 => (BlockFormat::Bc7, false),
+        146 => (BlockFormat::Bc7, true),
+        147 => (BlockFormat::Etc2Rgb, false),
+        148 => (BlockFormat::Etc2Rgb, true),
+        149 => (BlockFormat::Etc2RgbA1, false),
+        150 => (BlockFormat::Etc2RgbA1, true),
+        151 => (BlockFormat::Etc2Rgba, false),
+        152 => (BlockFormat::Etc2Rgba, true),
+        other => {
+            return Err(ContainerError::UnsupportedFormat(format!("VkFormat {}", other)));
+        }
+    };
+    let layers = layer_count.saturating_mul(face_count);
+    check_header(width, height, level_count, layers)?;
+
+    let mut levels = Vec::with_capacity(level_count as usize);
+    for level in 0..level_count as usize {
+        let index = 80 + level * 24;
+        let offset = read_u64(bytes, index)? as usize;
+        let length = read_u64(bytes, index + 8)? as usize;
+        let size = level_size(format, width, height, level, layers)?;
+        if length < size {
+            return Err(ContainerError::Truncated);
+        }
+        levels.push(slice(bytes, offset, size)?.to_vec());
+    }
+    Ok(CompressedImage {
+        format,
+        srgb,
+        width,
+        height,
+        layers: layers as hal::image::Layer,
+        levels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_u32(bytes: &mut [u8], offset: usize, value: u32) {
+        bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_u64(bytes: &mut [u8], offset: usize, value: u64) {
+        bytes[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    // 文件头之后是data_len字节的数据, 第i个字节的值是i
+    fn dds(width: u32, height: u32, level_count: u32, four_cc: &[u8; 4], caps2: u32, data_len: usize) -> Vec<u8> {
+        let mut bytes = vec![0u8; 128];
+        bytes[0..4].copy_from_slice(DDS_MAGIC);
+        put_u32(&mut bytes, 4, 124);
+        put_u32(&mut bytes, 12, height);
+        put_u32(&mut bytes, 16, width);
+        put_u32(&mut bytes, 28, level_count);
+        put_u32(&mut bytes, 76, 32);
+        put_u32(&mut bytes, 80, DDPF_FOURCC);
+        bytes[84..88].copy_from_slice(four_cc);
+        put_u32(&mut bytes, 112, caps2);
+        bytes.extend((0..data_len).map(|i| i as u8));
+        bytes
+    }
+
+    fn dds_dx10(
+        width: u32,
+        height: u32,
+        level_count: u32,
+        dxgi_format: u32,
+        misc_flags: u32,
+        array_size: u32,
+        data_len: usize,
+    ) -> Vec<u8> {
+        let mut bytes = dds(width, height, level_count, b"DX10", 0, 20);
+        // D3D10_RESOURCE_DIMENSION_TEXTURE2D
+        put_u32(&mut bytes, 128, dxgi_format);
+        put_u32(&mut bytes, 132, 3);
+        put_u32(&mut bytes, 136, misc_flags);
+        put_u32(&mut bytes, 140, array_size);
+        bytes.extend((0..data_len).map(|i| i as u8));
+        bytes
+    }
+
+    // 每个层级的数据依次放在层级索引之后, sizes是每个层级的字节数
+    fn ktx2(vk_format: u32, width: u32, height: u32, layer_count: u32, face_count: u32, sizes: &[usize]) -> Vec<u8> {
+        let mut bytes = vec![0u8; 80 + 24 * sizes.len()];
+        bytes[0..12].copy_from_slice(&KTX2_IDENTIFIER);
+        put_u32(&mut bytes, 12, vk_format);
+        put_u32(&mut bytes, 20, width);
+        put_u32(&mut bytes, 24, height);
+        put_u32(&mut bytes, 32, layer_count);
+        put_u32(&mut bytes, 36, face_count);
+        put_u32(&mut bytes, 40, sizes.len() as u32);
+        let mut offset = bytes.len();
+        for (level, &size) in sizes.iter().enumerate() {
+            let index = 80 + level * 24;
+            put_u64(&mut bytes, index, offset as u64);
+            put_u64(&mut bytes, index + 8, size as u64);
+            put_u64(&mut bytes, index + 16, size as u64);
+            offset += size;
+        }
+        let data_len = offset - bytes.len();
+        bytes.extend((0..data_len).map(|i| i as u8));
+        bytes
+    }
+
+    fn error(bytes: &[u8]) -> ContainerError {
+        match CompressedImage::parse(bytes) {
+            Ok(_) => panic!("parsed an invalid file"),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn dds_mip_chain() {
+        let bytes = dds(8, 8, 4, b"DXT1", 0, 56);
+        let image = CompressedImage::parse(&bytes).unwrap();
+        assert_eq!(image.format, BlockFormat::Bc1Rgba);
+        assert!(!image.srgb);
+        assert_eq!((image.width, image.height, image.layers), (8, 8, 1));
+        let sizes: Vec<_> = image.levels.iter().map(|level| level.len()).collect();
+        assert_eq!(sizes, [32, 8, 8, 8]);
+        assert_eq!(image.levels[1][..], bytes[160..168]);
+        assert_eq!(image.level_extent(3), (1, 1));
+        assert_eq!(image.layer_size(0), 32);
+    }
+
+    #[test]
+    fn dds_cubemap_is_stored_by_face() {
+        // 每个面包含所有层级: 4x4和2x2各一个16字节的块
+        let bytes = dds(4, 4, 2, b"DXT5", DDSCAPS2_CUBEMAP, 6 * 32);
+        let image = CompressedImage::parse(&bytes).unwrap();
+        assert_eq!(image.format, BlockFormat::Bc3);
+        assert_eq!(image.layers, 6);
+        assert_eq!(image.levels[0].len(), 96);
+        assert_eq!(image.levels[1].len(), 96);
+        // 第0层的第二个面在文件中位于第一个面的所有层级之后
+        assert_eq!(image.levels[0][16..32], bytes[160..176]);
+        assert_eq!(image.levels[1][0..16], bytes[144..160]);
+    }
+
+    #[test]
+    fn dds_dx10_array() {
+        let bytes = dds_dx10(4, 4, 1, 99, 0, 3, 48);
+        let image = CompressedImage::parse(&bytes).unwrap();
+        assert_eq!(image.format, BlockFormat::Bc7);
+        assert!(image.srgb);
+        assert_eq!(image.layers, 3);
+        assert_eq!(image.levels[0][..], bytes[148..196]);
+    }
+
+    #[test]
+    fn ktx2_levels() {
+        // 8x4是两个块, 4x2是一个块; 层数和面数为0时按1处理
+        let bytes = ktx2(145, 8, 4, 0, 0, &[32, 16]);
+        let image = CompressedImage::parse(&bytes).unwrap();
+        assert_eq!(image.format, BlockFormat::Bc7);
+        assert!(!image.srgb);
+        assert_eq!(image.layers, 1);
+        let sizes: Vec<_> = image.levels.iter().map(|level| level.len()).collect();
+        assert_eq!(sizes, [32, 16]);
+        assert_eq!(image.level_extent(1), (4, 2));
+    }
+
+    #[test]
+    fn ktx2_cubemap() {
+        let bytes = ktx2(134, 4, 4, 1, 6, &[48]);
+        let image = CompressedImage::parse(&bytes).unwrap();
+        assert_eq!(image.format, BlockFormat::Bc1Rgba);
+        assert!(image.srgb);
+        assert_eq!(image.layers, 6);
+        assert_eq!(image.levels[0].len(), 48);
+    }
+
+    #[test]
+    fn unknown_containers() {
+        assert_eq!(error(b""), ContainerError::UnknownContainer);
+        assert_eq!(error(b"\x89PNG\r\n\x1a\n"), ContainerError::UnknownContainer);
+        assert_eq!(error(&KTX1_IDENTIFIER), ContainerError::Unsupported("KTX1 files"));
+    }
+
+    #[test]
+    fn truncated_files() {
+        let files = [
+            dds(8, 8, 4, b"DXT1", 0, 56),
+            dds_dx10(4, 4, 1, 99, 0, 3, 48),
+            ktx2(145, 8, 4, 1, 1, &[32, 16]),
+        ];
+        for bytes in &files {
+            assert!(CompressedImage::parse(bytes).is_ok());
+            for len in 0..bytes.len() {
+                assert!(CompressedImage::parse(&bytes[..len]).is_err(), "{} bytes", len);
+            }
+        }
+    }
+
+    #[test]
+    fn level_count_is_limited_by_size() {
+        // 4x4最多3个层级: 4x4, 2x2, 1x1
+        assert!(CompressedImage::parse(&dds(4, 4, 3, b"DXT1", 0, 24)).is_ok());
+        let too_many = ContainerError::InvalidHeader("more mip levels than the size allows");
+        assert_eq!(error(&dds(4, 4, 4, b"DXT1", 0, 32)), too_many);
+        assert_eq!(error(&dds(4, 4, u32::MAX, b"DXT1", 0, 32)), too_many);
+        // 非正方形的图片按较长的边计算, 8x1的第0层是两个块
+        assert!(CompressedImage::parse(&dds(8, 1, 4, b"DXT1", 0, 40)).is_ok());
+        assert_eq!(error(&dds(8, 1, 5, b"DXT1", 0, 48)), too_many);
+
+        let mut bytes = ktx2(145, 8, 4, 1, 1, &[32, 16]);
+        put_u32(&mut bytes, 40, 100);
+        assert_eq!(error(&bytes), too_many);
+    }
+
+    #[test]
+    fn layer_count_is_limited() {
+        let too_many = ContainerError::InvalidHeader("too many array layers");
+        let cube = DDS_RESOURCE_MISC_TEXTURECUBE;
+        assert_eq!(error(&dds_dx10(4, 4, 1, 71, cube, u32::MAX, 16)), too_many);
+        assert_eq!(error(&dds_dx10(4, 4, 1, 71, 0, MAX_LAYERS + 1, 16)), too_many);
+        assert_eq!(error(&ktx2(133, 4, 4, u32::MAX, 6, &[8])), too_many);
+        assert_eq!(
+            error(&ktx2(133, 4, 4, 1, 3, &[8])),
+            ContainerError::InvalidHeader("face count must be 1 or 6"),
+        );
+    }
+
+    #[test]
+    fn huge_sizes_are_truncated() {
+        // 数据大小超出文件或者usize时都不会分配内存
+        assert_eq!(error(&dds(u32::MAX, u32::MAX, 1, b"DXT1", 0, 64)), ContainerError::Truncated);
+        assert_eq!(error(&dds_dx10(u32::MAX, u32::MAX, 32, 98, 0, 1, 64)), ContainerError::Truncated);
+        assert_eq!(error(&ktx2(145, u32::MAX, u32::MAX, 1, 1, &[16])), ContainerError::Truncated);
+        assert_eq!(error(&ktx2(145, 1 << 20, 1 << 20, 2048, 1, &[16])), ContainerError::Truncated);
+
+        let mut bytes = ktx2(145, 4, 4, 1, 1, &[16]);
+        put_u64(&mut bytes, 80, u64::MAX);
+        assert_eq!(error(&bytes), ContainerError::Truncated);
+    }
+
+    #[test]
+    fn empty_textures() {
+        let empty = ContainerError::Unsupported("empty textures");
+        assert_eq!(error(&dds(0, 4, 1, b"DXT1", 0, 8)), empty);
+        assert_eq!(error(&ktx2(145, 0, 4, 1, 1, &[16])), empty);
+    }
+}
@@ -0,0 +1,22 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// 只用于reflect.rs的测试: 和顶点着色器共用的绑定, 存储缓冲和带偏移量的推送常数
+
+layout(location = 0) in vec4 v_color;
+layout(location = 0) out vec4 target0;
+
+layout(set = 0, binding = 0) uniform Globals {
+    mat4 view_proj;
+} globals;
+layout(set = 0, binding = 1) buffer Lights {
+    vec4 colors[];
+} lights;
+
+layout(push_constant) uniform PushConstants {
+    layout(offset = 80) float exposure;
+} push;
+
+void main() {
+    target0 = v_color * lights.colors[0] * push.exposure * globals.view_proj[0][0];
+}
@@ -0,0 +1,29 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// 只用于reflect.rs的测试: 不连续的location, 整数输入, 描述符数组和推送常数
+
+layout(location = 0) in vec3 a_position;
+layout(location = 1) in ivec2 a_index;
+layout(location = 3) in vec4 a_color;
+layout(location = 0) out vec4 v_color;
+
+layout(set = 0, binding = 0) uniform Globals {
+    mat4 view_proj;
+} globals;
+layout(set = 1, binding = 2) uniform sampler2D u_textures[4];
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    vec4 tint;
+} push;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+void main() {
+    vec4 texel = textureLod(u_textures[1], vec2(a_index), 0.0);
+    v_color = a_color * push.tint * texel;
+    gl_Position = globals.view_proj * push.model * vec4(a_position, 1.0);
+}
@@ -0,0 +1,736 @@
+// 设备不支持压缩格式时, 在CPU上把压缩块解码成rgba8
+// 支持BC1到BC5和BC7; BC6H是HDR格式, ETC2的解码比较复杂, 这两类不支持CPU解码
+// BC4和BC5按照GPU采样的结果解码: 没有的通道为0, alpha为255
+
+use crate::container::BlockFormat;
+
+// 每个像素的字节数
+const PIXEL_SIZE: usize = 4;
+
+// 格式是否可以在CPU上解码
+pub fn supports(format: BlockFormat) -> bool {
+    match format {
+        BlockFormat::Bc1Rgb
+        | BlockFormat::Bc1Rgba
+        | BlockFormat::Bc2
+        | BlockFormat::Bc3
+        | BlockFormat::Bc4
+        | BlockFormat::Bc5
+        | BlockFormat::Bc7 => true,
+        BlockFormat::Bc6h
+        | BlockFormat::Etc2Rgb
+        | BlockFormat::Etc2RgbA1
+        | BlockFormat::Etc2Rgba => false,
+    }
+}
+
+// 把width x height的一层压缩数据解码成紧密排列的rgba8, 格式不支持时返回None
+pub fn decompress(format: BlockFormat, width: u32, height: u32, data: &[u8]) -> Option<Vec<u8>> {
+    let decode_block: fn(&[u8], &mut [[u8; 4]; 16]) = match format {
+        BlockFormat::Bc1Rgb => |block, texels| decode_bc1(block, texels, false),
+        BlockFormat::Bc1Rgba => |block, texels| decode_bc1(block, texels, true),
+        BlockFormat::Bc2 => decode_bc2,
+        BlockFormat::Bc3 => decode_bc3,
+        BlockFormat::Bc4 => decode_bc4,
+        BlockFormat::Bc5 => decode_bc5,
+        BlockFormat::Bc7 => decode_bc7,
+        _ => return None,
+    };
+    let block_size = format.block_size() as usize;
+    let (width, height) = (width as usize, height as usize);
+    let blocks_wide = (width + 3) / 4;
+    let blocks_high = (height + 3) / 4;
+    let mut pixels = vec![0u8; width * height * PIXEL_SIZE];
+    let mut texels = [[0u8; 4]; 16];
+    for block_y in 0..blocks_high {
+        for block_x in 0..blocks_wide {
+            let offset = (block_y * blocks_wide + block_x) * block_size;
+            decode_block(&data[offset..offset + block_size], &mut texels);
+            // 图片边缘的块只写入图片范围内的像素
+            for y in 0..4 {
+                for x in 0..4 {
+                    let (pixel_x, pixel_y) = (block_x * 4 + x, block_y * 4 + y);
+                    if pixel_x < width && pixel_y < height {
+                        let base = (pixel_y * width + pixel_x) * PIXEL_SIZE;
+                        pixels[base..base + PIXEL_SIZE].copy_from_slice(&texels[y * 4 + x]);
+                    }
+                }
+            }
+        }
+    }
+    Some(pixels)
+}
+
+fn read_u16(block: &[u8], offset: usize) -> u16 {
+    u16::from(block[offset]) | u16::from(block[offset + 1]) << 8
+}
+
+fn read_u32(block: &[u8], offset: usize) -> u32 {
+    u32::from(read_u16(block, offset)) | u32::from(read_u16(block, offset + 2)) << 16
+}
+
+fn read_u64(block: &[u8], offset: usize) -> u64 {
+    u64::from(read_u32(block, offset)) | u64::from(read_u32(block, offset + 4)) << 32
+}
+
+// rgb565展开成rgb888, 低位用高位填充
+fn rgb565(color: u16) -> [u32; 3] {
+    let r = u32::from(color >> 11) & 31;
+    let g = u32::from(color >> 5) & 63;
+    let b = u32::from(color) & 31;
+    [(r << 3) | (r >> 2), (g << 2) | (g >> 4), (b << 3) | (b >> 2)]
+}
+
+// BC1的颜色部分, BC2和BC3中的颜色块总是使用4色模式
+fn decode_color(block: &[u8], texels: &mut [[u8; 4]; 16], four_color: bool, alpha: bool) {
+    let color0 = read_u16(block, 0);
+    let color1 = read_u16(block, 2);
+    let indices = read_u32(block, 4);
+    let c0 = rgb565(color0);
+    let c1 = rgb565(color1);
+    let mut palette = [[0u8; 4]; 4];
+    for channel in 0..3 {
+        palette[0][channel] = c0[channel] as u8;
+        palette[1][channel] = c1[channel] as u8;
+        if four_color || color0 > color1 {
+            palette[2][channel] = ((2 * c0[channel] + c1[channel]) / 3) as u8;
+            palette[3][channel] = ((c0[channel] + 2 * c1[channel]) / 3) as u8;
+        } else {
+            palette[2][channel] = ((c0[channel] + c1[channel]) / 2) as u8;
+        }
+    }
+    palette[0][3] = 255;
+    palette[1][3] = 255;
+    palette[2][3] = 255;
+    // 3色模式中的第4种颜色是黑色, 带alpha时是透明的
+    palette[3][3] = if four_color || color0 > color1 || !alpha { 255 } else { 0 };
+    for (i, texel) in texels.iter_mut().enumerate() {
+        *texel = palette[(indices >> (2 * i) & 3) as usize];
+    }
+}
+
+// BC3的alpha块和BC4, BC5的通道块: 两个端点和16个3位的索引
+fn decode_channel(block: &[u8]) -> [u8; 16] {
+    let a0 = u32::from(block[0]);
+    let a1 = u32::from(block[1]);
+    let mut palette = [0u8; 8];
+    palette[0] = a0 as u8;
+    palette[1] = a1 as u8;
+    if a0 > a1 {
+        for i in 1..7 {
+            palette[i + 1] = (((7 - i as u32) * a0 + i as u32 * a1) / 7) as u8;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = (((5 - i as u32) * a0 + i as u32 * a1) / 5) as u8;
+        }
+        palette[6] = 0;
+        palette[7] = 255;
+    }
+    // 索引在后6个字节中
+    let indices = read_u64(block, 0) >> 16;
+    let mut values = [0u8; 16];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = palette[(indices >> (3 * i) & 7) as usize];
+    }
+    values
+}
+
+fn decode_bc1(block: &[u8], texels: &mut [[u8; 4]; 16], alpha: bool) {
+    decode_color(block, texels, false, alpha);
+}
+
+// BC2: 每个像素4位的显式alpha, 之后是颜色块
+fn decode_bc2(block: &[u8], texels: &mut [[u8; 4]; 16]) {
+    decode_color(&block[8..], texels, true, false);
+    let alpha = read_u64(block, 0);
+    for (i, texel) in texels.iter_mut().enumerate() {
+        texel[3] = (alpha >> (4 * i) & 15) as u8 * 17;
+    }
+}
+
+// BC3: 插值的alpha块, 之后是颜色块
+fn decode_bc3(block: &[u8], texels: &mut [[u8; 4]; 16]) {
+    decode_color(&block[8..], texels, true, false);
+    let alpha = decode_channel(block);
+    for (texel, alpha) in texels.iter_mut().zip(alpha.iter()) {
+        texel[3] = *alpha;
+    }
+}
+
+fn decode_bc4(block: &[u8], texels: &mut [[u8; 4]; 16]) {
+    let red = decode_channel(block);
+    for (texel, red) in texels.iter_mut().zip(red.iter()) {
+        *texel = [*red, 0, 0, 255];
+    }
+}
+
+fn decode_bc5(block: &[u8], texels: &mut [[u8; 4]; 16]) {
+    let red = decode_channel(block);
+    let green = decode_channel(&block[8..]);
+    for (i, texel) in texels.iter_mut().enumerate() {
+        *texel = [red[i], green[i], 0, 255];
+    }
+}
+
+// BC7的8种模式
+struct Bc7Mode {
+    // 子集的个数
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    // 每个端点一个p位
+    endpoint_pbits: bool,
+    // 每个子集共用一个p位
+    shared_pbits: bool,
+    index_bits: u32,
+    // 单独的alpha索引, 为0时颜色和alpha使用同一个索引
+    index_bits2: u32,
+}
+
+const BC7_MODES: [Bc7Mode; 8] = [
+    Bc7Mode { subsets: 3, partition_bits: 4, rotation_bits: 0, index_selection_bits: 0, color_bits: 4, alpha_bits: 0, endpoint_pbits: true, shared_pbits: false, index_bits: 3, index_bits2: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 6, alpha_bits: 0, endpoint_pbits: false, shared_pbits: true, index_bits: 3, index_bits2: 0 },
+    Bc7Mode { subsets: 3, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 0, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits2: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 0, endpoint_pbits: true, shared_pbits: false, index_bits: 2, index_bits2: 0 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 1, color_bits: 5, alpha_bits: 6, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits2: 3 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 0, color_bits: 7, alpha_bits: 8, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits2: 2 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 7, endpoint_pbits: true, shared_pbits: false, index_bits: 4, index_bits2: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 5, endpoint_pbits: true, shared_pbits: false, index_bits: 2, index_bits2: 0 },
+];
+
+// 2个子集的划分, 第i位是第i个像素所在的子集
+const BC7_PARTITIONS2: [u16; 64] = [
+    0xcccc, 0x8888, 0xeeee, 0xecc8, 0xc880, 0xfeec, 0xfec8, 0xec80,
+    0xc800, 0xffec, 0xfe80, 0xe800, 0xffe8, 0xff00, 0xfff0, 0xf000,
+    0xf710, 0x008e, 0x7100, 0x08ce, 0x008c, 0x7310, 0x3100, 0x8cce,
+    0x088c, 0x3110, 0x6666, 0x366c, 0x17e8, 0x0ff0, 0x718e, 0x399c,
+    0xaaaa, 0xf0f0, 0x5a5a, 0x33cc, 0x3c3c, 0x55aa, 0x9696, 0xa55a,
+    0x73ce, 0x13c8, 0x324c, 0x3bdc, 0x6996, 0xc33c, 0x9966, 0x0660,
+    0x0272, 0x04e4, 0x4e40, 0x2720, 0xc936, 0x936c, 0x39c6, 0x639c,
+    0x9336, 0x9cc6, 0x817e, 0xe718, 0xccf0, 0x0fcc, 0x7744, 0xee22,
+];
+
+// 3个子集的划分, 每个像素所在的子集
+const BC7_PARTITIONS3: [[u8; 16]; 64] = [
+    [0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 1, 2, 2, 2, 2],
+    [0, 0, 0, 1, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 2, 0, 0, 1, 2, 2, 1, 1, 2, 2, 1, 1],
+    [0, 2, 2, 2, 0, 0, 2, 2, 0, 0, 1, 1, 0, 1, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2],
+    [0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 2, 2, 0, 0, 2, 2],
+    [0, 0, 2, 2, 0, 0, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1],
+    [0, 0, 1, 1, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2],
+    [0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2],
+    [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2],
+    [0, 1, 1, 2, 0, 1, 1, 2, 0, 1, 1, 2, 0, 1, 1, 2],
+    [0, 1, 2, 2, 0, 1, 2, 2, 0, 1, 2, 2, 0, 1, 2, 2],
+    [0, 0, 1, 1, 0, 1, 1, 2, 1, 1, 2, 2, 1, 2, 2, 2],
+    [0, 0, 1, 1, 2, 0, 0, 1, 2, 2, 0, 0, 2, 2, 2, 0],
+    [0, 0, 0, 1, 0, 0, 1, 1, 0, 1, 1, 2, 1, 1, 2, 2],
+    [0, 1, 1, 1, 0, 0, 1, 1, 2, 0, 0, 1, 2, 2, 0, 0],
+    [0, 0, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2, 2],
+    [0, 0, 2, 2, 0, 0, 2, 2, 0, 0, 2, 2, 1, 1, 1, 1],
+    [0, 1, 1, 1, 0, 1, 1, 1, 0, 2, 2, 2, 0, 2, 2, 2],
+    [0, 0, 0, 1, 0, 0, 0, 1, 2, 2, 2, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 0, 0, 1, 1, 0, 1, 2, 2, 0, 1, 2, 2],
+    [0, 0, 0, 0, 1, 1, 0, 0, 2, 2, 1, 0, 2, 2, 1, 0],
+    [0, 1, 2, 2, 0, 1, 2, 2, 0, 0, 1, 1, 0, 0, 0, 0],
+    [0, 0, 1, 2, 0, 0, 1, 2, 1, 1, 2, 2, 2, 2, 2, 2],
+    [0, 1, 1, 0, 1, 2, 2, 1, 1, 2, 2, 1, 0, 1, 1, 0],
+    [0, 0, 0, 0, 0, 1, 1, 0, 1, 2, 2, 1, 1, 2, 2, 1],
+    [0, 0, 2, 2, 1, 1, 0, 2, 1, 1, 0, 2, 0, 0, 2, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 2, 0, 0, 2, 2, 2, 2, 2],
+    [0, 0, 1, 1, 0, 1, 2, 2, 0, 1, 2, 2, 0, 0, 1, 1],
+    [0, 0, 0, 0, 2, 0, 0, 0, 2, 2, 1, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 2, 2, 2],
+    [0, 2, 2, 2, 0, 0, 2, 2, 0, 0, 1, 2, 0, 0, 1, 1],
+    [0, 0, 1, 1, 0, 0, 1, 2, 0, 0, 2, 2, 0, 2, 2, 2],
+    [0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0],
+    [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 0, 0, 0, 0],
+    [0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0],
+    [0, 1, 2, 0, 2, 0, 1, 2, 1, 2, 0, 1, 0, 1, 2, 0],
+    [0, 0, 1, 1, 2, 2, 0, 0, 1, 1, 2, 2, 0, 0, 1, 1],
+    [0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 0, 0, 0, 0, 1, 1],
+    [0, 1, 0, 1, 0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 2, 1, 2, 1, 2, 1],
+    [0, 0, 2, 2, 1, 1, 2, 2, 0, 0, 2, 2, 1, 1, 2, 2],
+    [0, 0, 2, 2, 0, 0, 1, 1, 0, 0, 2, 2, 0, 0, 1, 1],
+    [0, 2, 2, 0, 1, 2, 2, 1, 0, 2, 2, 0, 1, 2, 2, 1],
+    [0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2, 0, 1, 0, 1],
+    [0, 0, 0, 0, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1],
+    [0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 2, 2, 2, 2],
+    [0, 2, 2, 2, 0, 1, 1, 1, 0, 2, 2, 2, 0, 1, 1, 1],
+    [0, 0, 0, 2, 1, 1, 1, 2, 0, 0, 0, 2, 1, 1, 1, 2],
+    [0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2],
+    [0, 2, 2, 2, 0, 1, 1, 1, 0, 1, 1, 1, 0, 2, 2, 2],
+    [0, 0, 0, 2, 1, 1, 1, 2, 1, 1, 1, 2, 0, 0, 0, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 1, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 2, 2, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 2, 2],
+    [0, 0, 2, 2, 1, 1, 2, 2, 1, 1, 2, 2, 0, 0, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2],
+    [0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 1],
+    [0, 2, 2, 2, 1, 2, 2, 2, 0, 2, 2, 2, 1, 2, 2, 2],
+    [0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 1, 1, 1, 2, 0, 1, 1, 2, 2, 0, 1, 2, 2, 2, 0],
+];
+
+// 子集1的锚点像素, 它的索引少存储一位 (子集0的锚点总是像素0)
+const BC7_ANCHORS2: [u8; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15,
+    15, 2, 8, 2, 2, 8, 8, 15, 2, 8, 2, 2, 8, 8, 2, 2,
+    15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6,
+    6, 2, 6, 8, 15, 15, 2, 2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+
+// 3个子集时子集1和子集2的锚点像素
+const BC7_ANCHORS3: [[u8; 64]; 2] = [
+    [
+        3, 3, 15, 15, 8, 3, 15, 15, 8, 8, 6, 6, 6, 5, 3, 3,
+        3, 3, 8, 15, 3, 3, 6, 10, 5, 8, 8, 6, 8, 5, 15, 15,
+        8, 15, 3, 5, 6, 10, 8, 15, 15, 3, 15, 5, 15, 15, 15, 15,
+        3, 15, 5, 5, 5, 8, 5, 10, 5, 10, 8, 13, 15, 12, 3, 3,
+    ],
+    [
+        15, 8, 8, 3, 15, 15, 3, 8, 15, 15, 15, 15, 15, 15, 15, 8,
+        15, 8, 15, 3, 15, 8, 15, 8, 3, 15, 6, 10, 15, 15, 10, 8,
+        15, 3, 15, 10, 10, 8, 9, 10, 6, 15, 8, 15, 3, 6, 6, 8,
+        15, 3, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 3, 15, 15, 8,
+    ],
+];
+
+// 2, 3, 4位索引的插值权重, 总和为64
+const BC7_WEIGHTS2: [u32; 4] = [0, 21, 43, 64];
+const BC7_WEIGHTS3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const BC7_WEIGHTS4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+// 从低位开始依次读取128位的块
+struct BitReader {
+    bits: u128,
+    position: u32,
+}
+
+impl BitReader {
+    fn read(&mut self, count: u32) -> u32 {
+        let value = (self.bits >> self.position) as u32 & ((1u32 << count) - 1);
+        self.position += count;
+        value
+    }
+}
+
+fn bc7_interpolate(e0: u32, e1: u32, index: u32, index_bits: u32) -> u8 {
+    let weight = match index_bits {
+        2 => BC7_WEIGHTS2[index as usize],
+        3 => BC7_WEIGHTS3[index as usize],
+        _ => BC7_WEIGHTS4[index as usize],
+    };
+    (((64 - weight) * e0 + weight * e1 + 32) >> 6) as u8
+}
+
+// n位的端点展开到8位, 低位用高位填充
+fn bc7_expand(value: u32, bits: u32) -> u32 {
+    let value = value << (8 - bits);
+    value | (value >> bits)
+}
+
+fn decode_bc7(block: &[u8], texels: &mut [[u8; 4]; 16]) {
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&block[..16]);
+    let bits = u128::from_le_bytes(bytes);
+    // 模式是最低的1所在的位置, 没有1时是保留的模式, 解码为透明的黑色
+    if bits & 0xff == 0 {
+        *texels = [[0; 4]; 16];
+        return;
+    }
+    let mode_index = bits.trailing_zeros();
+    let mode = &BC7_MODES[mode_index as usize];
+    let mut reader = BitReader { bits, position: mode_index + 1 };
+    let partition = reader.read(mode.partition_bits) as usize;
+    let rotation = reader.read(mode.rotation_bits);
+    let index_selection = reader.read(mode.index_selection_bits);
+
+    // 端点按通道存储: 先是所有端点的r, 然后是g, b, a
+    let endpoint_count = mode.subsets * 2;
+    let mut endpoints = [[0u32; 4]; 6];
+    for channel in 0..3 {
+        for endpoint in endpoints.iter_mut().take(endpoint_count) {
+            endpoint[channel] = reader.read(mode.color_bits);
+        }
+    }
+    for endpoint in endpoints.iter_mut().take(endpoint_count) {
+        endpoint[3] = reader.read(mode.alpha_bits);
+    }
+    let mut color_bits = mode.color_bits;
+    let mut alpha_bits = mode.alpha_bits;
+    if mode.endpoint_pbits || mode.shared_pbits {
+        let mut pbits = [0u32; 6];
+        if mode.endpoint_pbits {
+            for pbit in pbits.iter_mut().take(endpoint_count) {
+                *pbit = reader.read(1);
+            }
+        } else {
+            for subset in 0..mode.subsets {
+                let pbit = reader.read(1);
+                pbits[subset * 2] = pbit;
+                pbits[subset * 2 + 1] = pbit;
+            }
+        }
+        for (endpoint, pbit) in endpoints.iter_mut().zip(pbits.iter()).take(endpoint_count) {
+            for value in endpoint.iter_mut().take(3) {
+                *value = *value << 1 | pbit;
+            }
+            if mode.alpha_bits > 0 {
+                endpoint[3] = endpoint[3] << 1 | pbit;
+            }
+        }
+        color_bits += 1;
+        if mode.alpha_bits > 0 {
+            alpha_bits += 1;
+        }
+    }
+    for endpoint in endpoints.iter_mut().take(endpoint_count) {
+        for value in endpoint.iter_mut().take(3) {
+            *value = bc7_expand(*value, color_bits);
+        }
+        endpoint[3] = if alpha_bits > 0 { bc7_expand(endpoint[3], alpha_bits) } else { 255 };
+    }
+
+    let subset_of = |texel: usize| -> usize {
+        match mode.subsets {
+            2 => (BC7_PARTITIONS2[partition] >> texel & 1) as usize,
+            3 => BC7_PARTITIONS3[partition][texel] as usize,
+            _ => 0,
+        }
+    };
+    let is_anchor = |texel: usize| -> bool {
+        texel == 0
+            || match mode.subsets {
+                2 => texel == BC7_ANCHORS2[partition] as usize,
+                3 => {
+                    texel == BC7_ANCHORS3[0][partition] as usize
+                        || texel == BC7_ANCHORS3[1][partition] as usize
+                }
+                _ => false,
+            }
+    };
+    // 锚点像素的索引最高位总是0, 不存储
+    let mut indices = [0u32; 16];
+    for (texel, index) in indices.iter_mut().enumerate() {
+        let bits = if is_anchor(texel) { mode.index_bits - 1 } else { mode.index_bits };
+        *index = reader.read(bits);
+    }
+    let mut indices2 = [0u32; 16];
+    if mode.index_bits2 > 0 {
+        for (texel, index) in indices2.iter_mut().enumerate() {
+            let bits = if texel == 0 { mode.index_bits2 - 1 } else { mode.index_bits2 };
+            *index = reader.read(bits);
+        }
+    }
+
+    for (texel, output) in texels.iter_mut().enumerate() {
+        let subset = subset_of(texel);
+        let e0 = endpoints[subset * 2];
+        let e1 = endpoints[subset * 2 + 1];
+        // 有两组索引时, 索引选择位决定哪一组用于颜色
+        let (color_index, color_index_bits, alpha_index, alpha_index_bits) = if mode.index_bits2 == 0 {
+            (indices[texel], mode.index_bits, indices[texel], mode.index_bits)
+        } else if index_selection == 0 {
+            (indices[texel], mode.index_bits, indices2[texel], mode.index_bits2)
+        } else {
+            (indices2[texel], mode.index_bits2, indices[texel], mode.index_bits)
+        };
+        let mut color = [0u8; 4];
+        for channel in 0..3 {
+            color[channel] = bc7_interpolate(e0[channel], e1[channel], color_index, color_index_bits);
+        }
+        color[3] = bc7_interpolate(e0[3], e1[3], alpha_index, alpha_index_bits);
+        // 旋转: 把alpha和一个颜色通道交换
+        match rotation {
+            1 => color.swap(0, 3),
+            2 => color.swap(1, 3),
+            3 => color.swap(2, 3),
+            _ => {}
+        }
+        *output = color;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 解码一个4x4的块, 按行返回16个像素
+    fn decode(format: BlockFormat, block: &[u8]) -> Vec<[u8; 4]> {
+        decompress(format, 4, 4, block)
+            .unwrap()
+            .chunks(PIXEL_SIZE)
+            .map(|pixel| [pixel[0], pixel[1], pixel[2], pixel[3]])
+            .collect()
+    }
+
+    // 每行4个像素的索引依次是0, 1, 2, 3
+    const COLOR_INDICES: [u8; 4] = [0xe4, 0xe4, 0xe4, 0xe4];
+    // 第i个像素的3位索引是i % 8
+    const CHANNEL_INDICES: [u8; 6] = [0x88, 0xc6, 0xfa, 0x88, 0xc6, 0xfa];
+
+    // color0 = 红色 > color1 = 蓝色, 4色模式
+    fn red_blue_block() -> Vec<u8> {
+        let mut block = vec![0x00, 0xf8, 0x1f, 0x00];
+        block.extend_from_slice(&COLOR_INDICES);
+        block
+    }
+
+    // color0 = 蓝色 <= color1 = 红色, BC1使用3色模式
+    fn blue_red_block() -> Vec<u8> {
+        let mut block = vec![0x1f, 0x00, 0x00, 0xf8];
+        block.extend_from_slice(&COLOR_INDICES);
+        block
+    }
+
+    // a0 = 255 > a1 = 0, 8个插值
+    fn alpha_block_8() -> Vec<u8> {
+        let mut block = vec![255, 0];
+        block.extend_from_slice(&CHANNEL_INDICES);
+        block
+    }
+
+    // a0 = 40 <= a1 = 240, 6个插值加上0和255
+    fn alpha_block_6() -> Vec<u8> {
+        let mut block = vec![40, 240];
+        block.extend_from_slice(&CHANNEL_INDICES);
+        block
+    }
+
+    const ALPHA_8: [u8; 8] = [255, 0, 218, 182, 145, 109, 72, 36];
+    const ALPHA_6: [u8; 8] = [40, 240, 80, 120, 160, 200, 0, 255];
+
+    #[test]
+    fn supported_formats() {
+        assert!(supports(BlockFormat::Bc1Rgb));
+        assert!(supports(BlockFormat::Bc7));
+        assert!(!supports(BlockFormat::Bc6h));
+        assert!(!supports(BlockFormat::Etc2Rgba));
+        assert_eq!(decompress(BlockFormat::Bc6h, 4, 4, &[0; 16]), None);
+    }
+
+    #[test]
+    fn bc1_four_colors() {
+        let row = [[255, 0, 0, 255], [0, 0, 255, 255], [170, 0, 85, 255], [85, 0, 170, 255]];
+        let expected: Vec<[u8; 4]> = (0..16).map(|texel| row[texel % 4]).collect();
+        assert_eq!(decode(BlockFormat::Bc1Rgb, &red_blue_block()), expected);
+        assert_eq!(decode(BlockFormat::Bc1Rgba, &red_blue_block()), expected);
+    }
+
+    #[test]
+    fn bc1_three_colors() {
+        let row = [[0, 0, 255, 255], [255, 0, 0, 255], [127, 0, 127, 255], [0, 0, 0, 255]];
+        let expected: Vec<[u8; 4]> = (0..16).map(|texel| row[texel % 4]).collect();
+        assert_eq!(decode(BlockFormat::Bc1Rgb, &blue_red_block()), expected);
+    }
+
+    #[test]
+    fn bc1_transparent_black() {
+        let row = [[0, 0, 255, 255], [255, 0, 0, 255], [127, 0, 127, 255], [0, 0, 0, 0]];
+        let expected: Vec<[u8; 4]> = (0..16).map(|texel| row[texel % 4]).collect();
+        assert_eq!(decode(BlockFormat::Bc1Rgba, &blue_red_block()), expected);
+    }
+
+    // BC2的颜色总是4色模式, 即使color0 <= color1
+    #[test]
+    fn bc2_explicit_alpha() {
+        let mut block = vec![0x10, 0x32, 0x54, 0x76, 0x98, 0xba, 0xdc, 0xfe];
+        block.extend(blue_red_block());
+        let row = [[0, 0, 255], [255, 0, 0], [85, 0, 170], [170, 0, 85]];
+        let expected: Vec<[u8; 4]> = (0..16)
+            .map(|texel| {
+                let [r, g, b] = row[texel % 4];
+                [r, g, b, texel as u8 * 17]
+            })
+            .collect();
+        assert_eq!(decode(BlockFormat::Bc2, &block), expected);
+    }
+
+    #[test]
+    fn bc3_interpolated_alpha() {
+        let mut block = alpha_block_8();
+        block.extend(blue_red_block());
+        let row = [[0, 0, 255], [255, 0, 0], [85, 0, 170], [170, 0, 85]];
+        let expected: Vec<[u8; 4]> = (0..16)
+            .map(|texel| {
+                let [r, g, b] = row[texel % 4];
+                [r, g, b, ALPHA_8[texel % 8]]
+            })
+            .collect();
+        assert_eq!(decode(BlockFormat::Bc3, &block), expected);
+    }
+
+    #[test]
+    fn bc4_red() {
+        let expected: Vec<[u8; 4]> = (0..16).map(|texel| [ALPHA_6[texel % 8], 0, 0, 255]).collect();
+        assert_eq!(decode(BlockFormat::Bc4, &alpha_block_6()), expected);
+    }
+
+    #[test]
+    fn bc5_red_green() {
+        let mut block = alpha_block_6();
+        block.extend(alpha_block_8());
+        let expected: Vec<[u8; 4]> =
+            (0..16).map(|texel| [ALPHA_6[texel % 8], ALPHA_8[texel % 8], 0, 255]).collect();
+        assert_eq!(decode(BlockFormat::Bc5, &block), expected);
+    }
+
+    // 图片边缘的块只写入图片范围内的像素
+    #[test]
+    fn edge_blocks_are_cropped() {
+        // 两个BC4块: 第一个的像素都是40, 第二个的像素都是240
+        let mut data = vec![40, 240, 0, 0, 0, 0, 0, 0];
+        data.extend_from_slice(&[40, 240, 0x49, 0x92, 0x24, 0x49, 0x92, 0x24]);
+        let pixels = decompress(BlockFormat::Bc4, 5, 3, &data).unwrap();
+        assert_eq!(pixels.len(), 5 * 3 * PIXEL_SIZE);
+        for (i, pixel) in pixels.chunks(PIXEL_SIZE).enumerate() {
+            let red = if i % 5 < 4 { 40 } else { 240 };
+            assert_eq!(pixel, [red, 0, 0, 255]);
+        }
+    }
+
+    // 低8位全为0的块是保留的模式, 解码为透明的黑色
+    #[test]
+    fn bc7_reserved_mode() {
+        assert_eq!(decode(BlockFormat::Bc7, &[0; 16]), vec![[0, 0, 0, 0]; 16]);
+    }
+
+    // mode 0: 3个子集(分区0), 4位端点加每个端点的p位, 3位索引
+    #[test]
+    fn bc7_mode_0() {
+        let block = [
+            0xe1, 0x01, 0x7e, 0x18, 0x1e, 0xfe, 0x10, 0xe0,
+            0x61, 0xa9, 0xe5, 0xbb, 0xb1, 0x78, 0x44, 0xf3,
+        ];
+        let expected = [
+            [255, 8, 8, 255], [0, 247, 0, 255], [112, 112, 151, 255], [112, 112, 151, 255],
+            [72, 180, 2, 255], [219, 42, 7, 255], [220, 220, 43, 255], [77, 77, 186, 255],
+            [108, 146, 3, 255], [206, 140, 74, 255], [49, 115, 181, 255], [43, 43, 220, 255],
+            [93, 122, 151, 255], [115, 126, 136, 255], [184, 136, 89, 255], [115, 126, 136, 255],
+        ];
+        assert_eq!(decode(BlockFormat::Bc7, &block), expected);
+    }
+
+    // mode 1: 2个子集(分区0), 6位端点加每个子集共用的p位, 3位索引
+    #[test]
+    fn bc7_mode_1() {
+        let block = [
+            0x02, 0x3f, 0x50, 0xf0, 0xc0, 0x8f, 0x7a, 0x0a,
+            0xf5, 0x03, 0x11, 0x8d, 0xf5, 0xef, 0x72, 0x8a,
+        ];
+        let expected = [
+            [255, 2, 42, 255], [219, 38, 48, 255], [82, 149, 182, 255], [113, 144, 146, 255],
+            [109, 148, 65, 255], [73, 184, 71, 255], [210, 126, 36, 255], [241, 120, 0, 255],
+            [2, 255, 82, 255], [38, 219, 76, 255], [179, 132, 71, 255], [148, 137, 107, 255],
+            [148, 109, 59, 255], [184, 73, 53, 255], [51, 155, 217, 255], [82, 149, 182, 255],
+        ];
+        assert_eq!(decode(BlockFormat::Bc7, &block), expected);
+    }
+
+    // mode 2: 3个子集(分区0), 5位端点, 2位索引
+    #[test]
+    fn bc7_mode_2() {
+        let block = [
+            0x04, 0x3e, 0x00, 0x5f, 0x79, 0xf0, 0xc1, 0xa7,
+            0x14, 0x80, 0xff, 0xbd, 0x92, 0xb7, 0xc9, 0x37,
+        ];
+        let expected = [
+            [255, 0, 0, 255], [171, 84, 0, 255], [171, 171, 255, 255], [84, 84, 255, 255],
+            [0, 255, 0, 255], [84, 171, 0, 255], [84, 84, 255, 255], [255, 255, 255, 255],
+            [255, 0, 0, 255], [136, 165, 193, 255], [193, 165, 136, 255], [255, 255, 255, 255],
+            [247, 165, 82, 255], [193, 165, 136, 255], [136, 165, 193, 255], [82, 165, 247, 255],
+        ];
+        assert_eq!(decode(BlockFormat::Bc7, &block), expected);
+    }
+
+    // mode 3: 2个子集(分区13), 7位端点加每个端点的p位, 2位索引
+    #[test]
+    fn bc7_mode_3() {
+        let block = [
+            0xd8, 0xfc, 0x01, 0x14, 0x32, 0x10, 0x48, 0xa6,
+            0x00, 0xfe, 0x28, 0xa8, 0xc9, 0x73, 0x9c, 0xa6,
+        ];
+        let expected = [
+            [254, 128, 0, 255], [171, 128, 84, 255], [84, 129, 171, 255], [1, 129, 255, 255],
+            [171, 128, 84, 255], [84, 129, 171, 255], [1, 129, 255, 255], [254, 128, 0, 255],
+            [148, 93, 134, 255], [200, 40, 160, 255], [41, 201, 81, 255], [93, 148, 107, 255],
+            [200, 40, 160, 255], [41, 201, 81, 255], [93, 148, 107, 255], [93, 148, 107, 255],
+        ];
+        assert_eq!(decode(BlockFormat::Bc7, &block), expected);
+    }
+
+    // mode 4: 旋转1(交换r和alpha), 索引选择1(3位索引用于颜色)
+    #[test]
+    fn bc7_mode_4() {
+        let block = [
+            0xb0, 0x1f, 0x28, 0x0a, 0xfe, 0x5f, 0xc8, 0xc9,
+            0xc9, 0xc9, 0x89, 0xc6, 0xfa, 0x77, 0x39, 0x05,
+        ];
+        let expected = [
+            [255, 82, 0, 255], [178, 94, 36, 219], [97, 105, 72, 183], [20, 117, 108, 147],
+            [255, 130, 147, 108], [178, 142, 183, 72], [97, 153, 219, 36], [20, 165, 255, 0],
+            [255, 165, 255, 0], [178, 153, 219, 36], [97, 142, 183, 72], [20, 130, 147, 108],
+            [255, 117, 108, 147], [178, 105, 72, 183], [97, 94, 36, 219], [20, 82, 0, 255],
+        ];
+        assert_eq!(decode(BlockFormat::Bc7, &block), expected);
+    }
+
+    // mode 5: 旋转2(交换g和alpha), 颜色和alpha各用一组2位索引
+    #[test]
+    fn bc7_mode_5() {
+        let block = [
+            0xa0, 0x7f, 0x00, 0xe0, 0x0f, 0x04, 0x21, 0x47,
+            0xc8, 0x37, 0x4a, 0x99, 0xb3, 0xb1, 0xb1, 0xb1,
+        ];
+        let expected = [
+            [255, 140, 129, 0], [171, 200, 108, 84], [84, 17, 85, 171], [0, 77, 64, 255],
+            [0, 140, 64, 255], [84, 200, 85, 171], [171, 17, 108, 84], [255, 77, 129, 0],
+            [171, 140, 108, 84], [171, 200, 108, 84], [84, 17, 85, 171], [84, 77, 85, 171],
+            [255, 140, 129, 0], [0, 200, 64, 255], [255, 17, 129, 0], [0, 77, 64, 255],
+        ];
+        assert_eq!(decode(BlockFormat::Bc7, &block), expected);
+    }
+
+    // mode 6: 7位rgba端点加每个端点的p位, 4位索引
+    #[test]
+    fn bc7_mode_6() {
+        let block = [
+            0x40, 0xc0, 0x1f, 0x04, 0x06, 0x02, 0xff, 0x80,
+            0x10, 0x32, 0x54, 0x76, 0x98, 0xba, 0xdc, 0xfe,
+        ];
+        let expected = [
+            [1, 65, 129, 255], [17, 73, 129, 239], [37, 83, 129, 219], [52, 91, 129, 203],
+            [68, 99, 129, 187], [84, 107, 129, 171], [104, 117, 129, 151], [120, 125, 129, 135],
+            [135, 132, 128, 120], [151, 140, 128, 104], [171, 150, 128, 84], [187, 158, 128, 68],
+            [203, 166, 128, 52], [218, 174, 128, 36], [238, 184, 128, 16], [254, 192, 128, 0],
+        ];
+        assert_eq!(decode(BlockFormat::Bc7, &block), expected);
+    }
+
+    // mode 7: 2个子集(分区0), 5位rgba端点加每个端点的p位, 2位索引
+    #[test]
+    fn bc7_mode_7() {
+        let block = [
+            0x80, 0xc0, 0x07, 0xe0, 0x83, 0x0f, 0x3e, 0x00,
+            0xff, 0x7f, 0x00, 0xff, 0x3a, 0x6d, 0xc8, 0x37,
+        ];
+        let expected = [
+            [255, 4, 4, 255], [4, 255, 4, 4], [84, 84, 252, 171], [171, 171, 254, 214],
+            [86, 173, 4, 86], [173, 86, 4, 173], [255, 255, 255, 255], [0, 0, 251, 130],
+            [255, 4, 4, 255], [173, 86, 4, 173], [171, 171, 254, 214], [255, 255, 255, 255],
+            [4, 255, 4, 4], [86, 173, 4, 86], [84, 84, 252, 171], [0, 0, 251, 130],
+        ];
+        assert_eq!(decode(BlockFormat::Bc7, &block), expected);
+    }
+}
@@ -0,0 +1,133 @@
+// 深度附件: 选择设备支持的深度格式, 按照交换链的尺寸创建深度图像
+
+use hal::{
+    adapter::PhysicalDevice,
+    device::Device,
+};
+
+use crate::Back;
+use crate::error::{AppError, Stage};
+use crate::memory::{Allocation, Allocator, Strategy};
+
+// 按优先级排列的深度格式, D32Float精度最高, 部分设备只支持带模板的格式
+const DEPTH_FORMATS: [hal::format::Format; 3] = [
+    hal::format::Format::D32Float,
+    hal::format::Format::D24UnormS8Uint,
+    hal::format::Format::D32FloatS8Uint,
+];
+
+// 选择第一个可以在optimal tiling下作为深度附件的格式, 都不支持时返回None
+pub fn choose_depth_format(
+    physical_device: &<Back as hal::Backend>::PhysicalDevice,
+) -> Option<hal::format::Format>
+{
+    DEPTH_FORMATS
+        .iter()
+        .cloned()
+        .find(|&format| {
+            physical_device
+                .format_properties(Some(format))
+                .optimal_tiling
+                .contains(hal::format::ImageFeature::DEPTH_STENCIL_ATTACHMENT)
+        })
+}
+
+// 采样数必须和颜色附件相同
+pub struct DepthTarget {
+    image: <Back as hal::Backend>::Image,
+    memory: Allocation,
+    pub view: <Back as hal::Backend>::ImageView,
+}
+
+impl DepthTarget {
+    pub fn new(
+        device: &<Back as hal::Backend>::Device,
+        allocator: &mut Allocator,
+        format: hal::format::Format,
+        extent: hal::image::Extent,
+        samples: hal::image::NumSamples,
+    ) -> Result<Self, AppError>
+    {
+        // 带模板的格式, 视图需要同时包含深度和模板
+        let (image, memory, view) = create_attachment(
+            device,
+            allocator,
+            format,
+            extent,
+            samples,
+            hal::image::Usage::DEPTH_STENCIL_ATTACHMENT,
+            format.surface_desc().aspects,
+        ).stage("create depth target")?;
+        Ok(DepthTarget { image, memory, view })
+    }
+
+    // 销毁深度图像, 调用前需要确保设备已经不再使用它
+    pub unsafe fn destroy(self, device: &<Back as hal::Backend>::Device, allocator: &mut Allocator) {
+        device.destroy_image_view(self.view);
+        device.destroy_image(self.image);
+        allocator.free(device, self.memory);
+    }
+}
+
+// 创建一个单层, 单个mip级别的附件图像, 绑定内存并创建视图
+// 深度附件和多重采样的颜色附件共用, 任何一步失败时销毁已经创建的对象
+pub(crate) fn create_attachment(
+    device: &<Back as hal::Backend>::Device,
+    allocator: &mut Allocator,
+    format: hal::format::Format,
+    extent: hal::image::Extent,
+    samples: hal::image::NumSamples,
+    usage: hal::image::Usage,
+    aspects: hal::format::Aspects,
+) -> Result<(<Back as hal::Backend>::Image, Allocation, <Back as hal::Backend>::ImageView), AppError>
+{
+    let mut image = unsafe {
+        device.create_image(
+            hal::image::Kind::D2(extent.width, extent.height, 1, samples),
+            1,
+            format,
+            hal::image::Tiling::Optimal,
+            usage,
+            hal::image::ViewCapabilities::empty(),
+        )
+    }.stage("create image")?;
+    let memory = unsafe {
+        allocator.bind_image(
+            device,
+            &mut image,
+            hal::memory::Properties::empty(),
+            hal::memory::Properties::DEVICE_LOCAL,
+            Strategy::General,
+        )
+    }.stage("allocate image memory");
+    let memory = match memory {
+        Ok(memory) => memory,
+        Err(err) => {
+            unsafe { device.destroy_image(image) };
+            return Err(err);
+        }
+    };
+    let view = unsafe {
+        device.create_image_view(
+            &image,
+            hal::image::ViewKind::D2,
+            format,
+            hal::format::Swizzle::NO,
+            hal::image::SubresourceRange {
+                aspects,
+                levels: 0..1,
+                layers: 0..1,
+            },
+        )
+    }.stage("create image view");
+    match view {
+        Ok(view) => Ok((image, memory, view)),
+        Err(err) => {
+            unsafe {
+                device.destroy_image(image);
+                allocator.free(device, memory);
+            }
+            Err(err)
+        }
+    }
+}
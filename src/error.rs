@@ -0,0 +1,168 @@
+// 统一的错误类型: 包装hal, winit和image的错误, 初始化和渲染失败时返回给调用者
+// 用stage标记出错的步骤, 例如 "create swapchain: ..."
+
+use std::fmt;
+
+use crate::adapter::AdapterOverride;
+use crate::container::ContainerError;
+use crate::memory::MemoryError;
+use crate::reflect::ReflectError;
+
+#[derive(Debug)]
+pub enum AppError {
+    // 创建窗口失败
+    Window(winit::CreationError),
+    // 命令行参数或者环境变量中的设置无效
+    Setting {
+        name: &'static str,
+        value: String,
+    },
+    // 没有可以使用的适配器
+    NoAdapter,
+    // 手动指定的适配器不存在或者不可用
+    AdapterOverride(AdapterOverride),
+    // 打开逻辑设备失败
+    Device(hal::error::DeviceCreationError),
+    // 创建管线失败
+    Creation(hal::pso::CreationError),
+    // 创建各种对象时内存不足
+    OutOfMemory(hal::device::OutOfMemory),
+    // 等待栅栏时内存不足或者设备丢失
+    Wait(hal::device::OomOrDeviceLost),
+    // 等待设备空闲失败
+    HostExecution(hal::error::HostExecutionError),
+    Buffer(hal::buffer::CreationError),
+    Image(hal::image::CreationError),
+    ImageView(hal::image::ViewError),
+    Bind(hal::device::BindError),
+    Mapping(hal::mapping::Error),
+    // 创建查询池失败
+    Query(hal::query::CreationError),
+    // 创建采样器等对象时分配失败
+    Allocation(hal::device::AllocationError),
+    DescriptorSet(hal::pso::AllocationError),
+    Memory(MemoryError),
+    Swapchain(hal::window::CreationError),
+    // 获取交换链图像失败
+    Acquire(hal::AcquireError),
+    // 呈现失败, hal没有提供失败的原因
+    Present,
+    ShaderModule(hal::device::ShaderError),
+    // glsl编译失败, 内容是编译器的错误信息
+    ShaderCompile(String),
+    Reflect(ReflectError),
+    // 图片解码失败
+    Decode(image::ImageError),
+    // DDS或者KTX2文件无效或者不支持
+    Container(ContainerError),
+    // 保存文件失败
+    Io(std::io::Error),
+    // 出错的步骤和原因
+    Stage {
+        stage: &'static str,
+        source: Box<AppError>,
+    },
+}
+
+impl AppError {
+    // 去掉所有的stage, 得到最初的错误
+    pub fn root(&self) -> &AppError {
+        match self {
+            AppError::Stage { source, .. } => source.root(),
+            err => err,
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AppError::Window(err) => write!(f, "cannot create window: {}", err),
+            AppError::Setting { name, value } => write!(f, "invalid {}: {}", name, value),
+            AppError::NoAdapter => write!(f, "no suitable adapter"),
+            AppError::AdapterOverride(AdapterOverride::Index(index)) => {
+                write!(f, "adapter {} does not exist or cannot be used", index)
+            }
+            AppError::AdapterOverride(AdapterOverride::Name(name)) => {
+                write!(f, "no usable adapter name contains \"{}\"", name)
+            }
+            AppError::Device(err) => write!(f, "cannot open device: {:?}", err),
+            AppError::Creation(err) => write!(f, "cannot create pipeline: {:?}", err),
+            AppError::OutOfMemory(err) => write!(f, "{:?}", err),
+            AppError::Wait(err) => write!(f, "cannot wait for fence: {:?}", err),
+            AppError::HostExecution(err) => write!(f, "cannot wait for device: {:?}", err),
+            AppError::Buffer(err) => write!(f, "cannot create buffer: {:?}", err),
+            AppError::Image(err) => write!(f, "cannot create image: {:?}", err),
+            AppError::ImageView(err) => write!(f, "cannot create image view: {:?}", err),
+            AppError::Bind(err) => write!(f, "cannot bind memory: {:?}", err),
+            AppError::Mapping(err) => write!(f, "cannot map memory: {:?}", err),
+            AppError::Query(err) => write!(f, "cannot create query pool: {:?}", err),
+            AppError::Allocation(err) => write!(f, "allocation failed: {:?}", err),
+            AppError::DescriptorSet(err) => write!(f, "cannot allocate descriptor set: {:?}", err),
+            AppError::Memory(err) => write!(f, "{}", err),
+            AppError::Swapchain(err) => write!(f, "cannot create swapchain: {:?}", err),
+            AppError::Acquire(err) => write!(f, "cannot acquire swapchain image: {:?}", err),
+            AppError::Present => write!(f, "cannot present swapchain image"),
+            AppError::ShaderModule(err) => write!(f, "cannot create shader module: {:?}", err),
+            AppError::ShaderCompile(message) => write!(f, "cannot compile shaders:\n{}", message),
+            AppError::Reflect(err) => write!(f, "shader reflection failed: {}", err),
+            AppError::Decode(err) => write!(f, "cannot decode image: {}", err),
+            AppError::Container(err) => write!(f, "cannot read texture container: {}", err),
+            AppError::Io(err) => write!(f, "{}", err),
+            AppError::Stage { stage, source } => write!(f, "{}: {}", stage, source),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+macro_rules! from_errors {
+    ($($error:ty => $variant:ident,)*) => {
+        $(
+            impl From<$error> for AppError {
+                fn from(err: $error) -> Self {
+                    AppError::$variant(err)
+                }
+            }
+        )*
+    };
+}
+
+from_errors! {
+    winit::CreationError => Window,
+    hal::error::DeviceCreationError => Device,
+    hal::pso::CreationError => Creation,
+    hal::device::OutOfMemory => OutOfMemory,
+    hal::device::OomOrDeviceLost => Wait,
+    hal::error::HostExecutionError => HostExecution,
+    hal::buffer::CreationError => Buffer,
+    hal::image::CreationError => Image,
+    hal::image::ViewError => ImageView,
+    hal::device::BindError => Bind,
+    hal::mapping::Error => Mapping,
+    hal::query::CreationError => Query,
+    hal::device::AllocationError => Allocation,
+    hal::pso::AllocationError => DescriptorSet,
+    MemoryError => Memory,
+    hal::window::CreationError => Swapchain,
+    hal::AcquireError => Acquire,
+    hal::device::ShaderError => ShaderModule,
+    ReflectError => Reflect,
+    image::ImageError => Decode,
+    ContainerError => Container,
+    std::io::Error => Io,
+}
+
+// 给Result加上出错的步骤
+pub(crate) trait Stage<T> {
+    fn stage(self, stage: &'static str) -> Result<T, AppError>;
+}
+
+impl<T, E: Into<AppError>> Stage<T> for Result<T, E> {
+    fn stage(self, stage: &'static str) -> Result<T, AppError> {
+        self.map_err(|err| AppError::Stage {
+            stage,
+            source: Box::new(err.into()),
+        })
+    }
+}
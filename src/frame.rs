@@ -0,0 +1,453 @@
+// 每一帧的命令和同步对象, 以及按顺序轮流使用它们的环
+//
+// 同步规则:
+// 1. begin_frame先等待这一帧上一次提交的栅栏, 之后才能重置命令池, 复用命令缓冲和信号量
+// 2. 获取交换链图像时使用这一帧自己的image_acquired信号量, 上一次提交已经等待过它, 可以安全复用
+// 3. 获取图像失败时不重置栅栏, 否则下一次begin_frame会一直等待一个不会再发出信号的栅栏
+//    规则1和3的顺序由start_frame实现, begin_frame和测试都通过它开始一帧
+// 4. end_frame提交时等待image_acquired, 完成后发出submission_complete和栅栏, 呈现等待submission_complete
+// 5. 开启GPU计时时, 时间戳查询也按帧划分, 等待栅栏之后才能读取这一帧上一次的结果
+//    时间戳由begin_timestamp和end_timestamp写在render pass前后, 不包括命令缓冲中的其他命令
+
+use hal::{
+    device::Device,
+    window::Swapchain,
+};
+
+use crate::Back;
+use crate::error::{AppError, Stage};
+use crate::timing::GpuTimer;
+
+pub struct FrameContext {
+    cmd_pool: hal::CommandPool<Back, hal::Graphics>,
+    cmd_buffer: hal::command::CommandBuffer<Back, hal::Graphics, hal::command::MultiShot>,
+    // 获取到交换链图像时发出信号
+    image_acquired: <Back as hal::Backend>::Semaphore,
+    // 这一帧的命令执行完成时发出信号, 呈现等待它
+    submission_complete: <Back as hal::Backend>::Semaphore,
+    // 这一帧的命令执行完成时发出信号, CPU等待它
+    submission_fence: <Back as hal::Backend>::Fence,
+}
+
+impl FrameContext {
+    fn new(
+        device: &<Back as hal::Backend>::Device,
+        queue_group: &hal::QueueGroup<Back, hal::Graphics>,
+    ) -> Result<Self, AppError>
+    {
+        // 逐个创建, 出错时销毁已经创建的对象
+        let image_acquired = device.create_semaphore().stage("create semaphore")?;
+        let submission_complete = match device.create_semaphore().stage("create semaphore") {
+            Ok(semaphore) => semaphore,
+            Err(err) => {
+                unsafe {
+                    device.destroy_semaphore(image_acquired);
+                }
+                return Err(err);
+            }
+        };
+        // 初始为有信号, 否则第一次等待会一直阻塞
+        let submission_fence = match device.create_fence(true).stage("create fence") {
+            Ok(fence) => fence,
+            Err(err) => {
+                unsafe {
+                    device.destroy_semaphore(image_acquired);
+                    device.destroy_semaphore(submission_complete);
+                }
+                return Err(err);
+            }
+        };
+        // 在真实的用例中, 通常认为每帧每个线程配置一个命令池是最佳的
+        let cmd_pool = unsafe {
+            device.create_command_pool_typed(
+                queue_group,
+                hal::pool::CommandPoolCreateFlags::empty(),
+            )
+        }.stage("create command pool");
+        let mut cmd_pool = match cmd_pool {
+            Ok(cmd_pool) => cmd_pool,
+            Err(err) => {
+                unsafe {
+                    device.destroy_semaphore(image_acquired);
+                    device.destroy_semaphore(submission_complete);
+                    device.destroy_fence(submission_fence);
+                }
+                return Err(err);
+            }
+        };
+        // 命令缓冲可以提交多次
+        let cmd_buffer = cmd_pool.acquire_command_buffer::<hal::command::MultiShot>();
+        Ok(FrameContext {
+            cmd_pool,
+            cmd_buffer,
+            image_acquired,
+            submission_complete,
+            submission_fence,
+        })
+    }
+
+    unsafe fn destroy(self, device: &<Back as hal::Backend>::Device) {
+        device.destroy_command_pool(self.cmd_pool.into_raw());
+        device.destroy_semaphore(self.image_acquired);
+        device.destroy_semaphore(self.submission_complete);
+        device.destroy_fence(self.submission_fence);
+    }
+}
+
+// 开始一帧时对当前FrameContext的操作, begin_frame中由设备实现, 测试中不依赖设备
+trait FrameStart {
+    // 等待这一帧上一次提交的栅栏
+    fn wait_fence(&mut self) -> Result<(), AppError>;
+    fn acquire_image(&mut self) -> Result<hal::SwapImageIndex, AppError>;
+    fn reset_fence(&mut self) -> Result<(), AppError>;
+}
+
+// 等待栅栏, 获取交换链图像, 获取成功后才重置栅栏, 见同步规则1和3
+// 获取失败时这一帧不会提交, 栅栏保持有信号, 下一次开始时不会一直等待
+fn start_frame<F: FrameStart>(frame: &mut F) -> Result<hal::SwapImageIndex, AppError> {
+    frame.wait_fence()?;
+    let image = frame.acquire_image()?;
+    frame.reset_fence()?;
+    Ok(image)
+}
+
+// begin_frame使用的FrameStart, 等待栅栏之后读取这一帧上一次的GPU时间
+struct DeviceFrameStart<'a> {
+    device: &'a <Back as hal::Backend>::Device,
+    swap_chain: &'a mut <Back as hal::Backend>::Swapchain,
+    frame: &'a FrameContext,
+    gpu_timer: Option<&'a mut GpuTimer>,
+    index: usize,
+}
+
+impl<'a> FrameStart for DeviceFrameStart<'a> {
+    fn wait_fence(&mut self) -> Result<(), AppError> {
+        unsafe {
+            self.device
+                .wait_for_fence(&self.frame.submission_fence, !0)
+                .stage("wait for frame")?;
+            if let Some(ref mut timer) = self.gpu_timer {
+                timer.read(self.device, self.index)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn acquire_image(&mut self) -> Result<hal::SwapImageIndex, AppError> {
+        unsafe {
+            self.swap_chain
+                .acquire_image(!0, hal::window::FrameSync::Semaphore(&self.frame.image_acquired))
+        }.map_err(AppError::Acquire)
+    }
+
+    fn reset_fence(&mut self) -> Result<(), AppError> {
+        unsafe { self.device.reset_fence(&self.frame.submission_fence) }.stage("reset frame fence")
+    }
+}
+
+// 帧的调度, 不依赖设备: 决定当前使用哪个FrameContext
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Schedule {
+    // FrameContext的个数
+    count: usize,
+    // 已经提交的帧数, 对count取余就是当前使用的FrameContext
+    frame: u64,
+}
+
+impl Schedule {
+    fn new(count: usize) -> Self {
+        assert_ne!(count, 0);
+        Schedule { count, frame: 0 }
+    }
+
+    fn index(&self) -> usize {
+        (self.frame % self.count as u64) as usize
+    }
+
+    // 提交之后切换到下一帧, 无论呈现是否成功
+    fn submitted(&mut self) {
+        self.frame += 1;
+    }
+}
+
+pub struct FrameRing {
+    frames: Vec<FrameContext>,
+    schedule: Schedule,
+    // 没有开启GPU计时时为None
+    gpu_timer: Option<GpuTimer>,
+}
+
+impl FrameRing {
+    // 创建count个FrameContext, 也就是最多可以同时渲染的帧数
+    // timestamp_period不为None时开启GPU计时, 它是时间戳每增加1经过的纳秒数
+    // 出错时销毁已经创建的FrameContext
+    pub fn new(
+        device: &<Back as hal::Backend>::Device,
+        queue_group: &hal::QueueGroup<Back, hal::Graphics>,
+        count: usize,
+        timestamp_period: Option<f32>,
+    ) -> Result<Self, AppError>
+    {
+        let mut ring = FrameRing {
+            frames: Vec::with_capacity(count),
+            schedule: Schedule::new(count),
+            gpu_timer: None,
+        };
+        for _ in 0..count {
+            match FrameContext::new(device, queue_group) {
+                Ok(frame) => ring.frames.push(frame),
+                Err(err) => {
+                    unsafe { ring.destroy(device) };
+                    return Err(err);
+                }
+            }
+        }
+        if let Some(period) = timestamp_period {
+            match GpuTimer::new(device, count, period) {
+                Ok(timer) => ring.gpu_timer = Some(timer),
+                Err(err) => {
+                    unsafe { ring.destroy(device) };
+                    return Err(err);
+                }
+            }
+        }
+        Ok(ring)
+    }
+
+    // 最近一次读回的GPU时间, 是frames_in_flight帧之前的结果
+    pub fn gpu_time(&self) -> Option<std::time::Duration> {
+        self.gpu_timer.as_ref().and_then(|timer| timer.last())
+    }
+
+    // 已经提交的帧数
+    pub fn frame(&self) -> u64 {
+        self.schedule.frame
+    }
+
+    fn current(&mut self) -> &mut FrameContext {
+        let index = self.schedule.index();
+        &mut self.frames[index]
+    }
+
+    // 等待当前帧上一次的提交完成, 获取下一张交换链图像, 然后开始记录命令
+    // 获取失败时返回AppError::Acquire, 交换链过期时需要重建交换链后再次调用
+    pub unsafe fn begin_frame(
+        &mut self,
+        device: &<Back as hal::Backend>::Device,
+        swap_chain: &mut <Back as hal::Backend>::Swapchain,
+    ) -> Result<hal::SwapImageIndex, AppError>
+    {
+        let index = self.schedule.index();
+        let image = start_frame(&mut DeviceFrameStart {
+            device,
+            swap_chain,
+            frame: &self.frames[index],
+            gpu_timer: self.gpu_timer.as_mut(),
+            index,
+        })?;
+        let frame = &mut self.frames[index];
+        frame.cmd_pool.reset();
+        frame.cmd_buffer.begin(false);
+        Ok(image)
+    }
+
+    // 当前帧的命令缓冲, 在begin_frame和end_frame之间记录命令
+    pub fn cmd_buffer(
+        &mut self,
+    ) -> &mut hal::command::CommandBuffer<Back, hal::Graphics, hal::command::MultiShot>
+    {
+        &mut self.current().cmd_buffer
+    }
+
+    // 在当前帧的render pass开始之前写入时间戳, 没有开启GPU计时时什么也不做
+    pub unsafe fn begin_timestamp(&mut self) {
+        let index = self.schedule.index();
+        if let Some(ref mut timer) = self.gpu_timer {
+            timer.begin(&mut self.frames[index].cmd_buffer, index);
+        }
+    }
+
+    // 在当前帧的render pass结束之后写入时间戳, 需要先调用begin_timestamp
+    pub unsafe fn end_timestamp(&mut self) {
+        let index = self.schedule.index();
+        if let Some(ref mut timer) = self.gpu_timer {
+            timer.end(&mut self.frames[index].cmd_buffer, index);
+        }
+    }
+
+    // 结束记录, 提交命令并呈现image, 然后切换到下一帧
+    // 提交之后无论呈现是否成功都会切换, 呈现失败时返回AppError::Present
+    pub unsafe fn end_frame(
+        &mut self,
+        queue: &mut hal::CommandQueue<Back, hal::Graphics>,
+        swap_chain: &<Back as hal::Backend>::Swapchain,
+        image: hal::SwapImageIndex,
+    ) -> Result<(), AppError>
+    {
+        let index = self.schedule.index();
+        let frame = &mut self.frames[index];
+        frame.cmd_buffer.finish();
+        let submission = hal::queue::Submission {
+            command_buffers: Some(&frame.cmd_buffer),
+            wait_semaphores: Some((
+                &frame.image_acquired,
+                hal::pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+            )),
+            signal_semaphores: Some(&frame.submission_complete),
+        };
+        queue.submit(submission, Some(&frame.submission_fence));
+        let present = swap_chain.present(queue, image, Some(&frame.submission_complete));
+        self.schedule.submitted();
+        present.map_err(|()| AppError::Present)
+    }
+
+    // 等待所有已经提交的帧完成
+    pub fn wait_all(&self, device: &<Back as hal::Backend>::Device) -> Result<(), AppError> {
+        unsafe {
+            device.wait_for_fences(
+                self.frames.iter().map(|frame| &frame.submission_fence),
+                hal::device::WaitFor::All,
+                !0,
+            )
+        }.stage("wait for frames")?;
+        Ok(())
+    }
+
+    // 销毁所有帧的资源, 调用前需要确保设备已经空闲
+    pub unsafe fn destroy(self, device: &<Back as hal::Backend>::Device) {
+        for frame in self.frames {
+            frame.destroy(device);
+        }
+        if let Some(timer) = self.gpu_timer {
+            timer.destroy(device);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{start_frame, FrameStart, Schedule};
+    use crate::error::AppError;
+
+    // 不依赖设备的FrameContext, 记录start_frame调用的顺序
+    struct MockFrame {
+        signaled: bool,
+        // 下一次获取到的图像, None表示交换链过期
+        image: Option<hal::SwapImageIndex>,
+        calls: Vec<&'static str>,
+    }
+
+    impl MockFrame {
+        fn new() -> Self {
+            MockFrame { signaled: true, image: Some(0), calls: Vec::new() }
+        }
+
+        // 提交后GPU立即完成, 栅栏发出信号
+        fn complete(&mut self) {
+            self.signaled = true;
+        }
+    }
+
+    impl FrameStart for MockFrame {
+        // 栅栏没有信号并且不会再提交时, 真实的设备会一直等待
+        fn wait_fence(&mut self) -> Result<(), AppError> {
+            assert!(self.signaled, "waiting for a fence that is never signaled");
+            self.calls.push("wait");
+            Ok(())
+        }
+
+        fn acquire_image(&mut self) -> Result<hal::SwapImageIndex, AppError> {
+            self.calls.push("acquire");
+            self.image.ok_or(AppError::Acquire(hal::AcquireError::OutOfDate))
+        }
+
+        fn reset_fence(&mut self) -> Result<(), AppError> {
+            self.calls.push("reset");
+            self.signaled = false;
+            Ok(())
+        }
+    }
+
+    fn assert_out_of_date(result: Result<hal::SwapImageIndex, AppError>) {
+        match result {
+            Err(AppError::Acquire(hal::AcquireError::OutOfDate)) => (),
+            other => panic!("expected an out of date swapchain, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn index_wraps_around() {
+        let mut schedule = Schedule::new(3);
+        let indices: Vec<_> = (0..7)
+            .map(|_| {
+                let index = schedule.index();
+                schedule.submitted();
+                index
+            })
+            .collect();
+        assert_eq!(indices, [0, 1, 2, 0, 1, 2, 0]);
+        assert_eq!(schedule.frame, 7);
+        assert_eq!(schedule.index(), 1);
+    }
+
+    #[test]
+    fn single_frame() {
+        let mut schedule = Schedule::new(1);
+        for _ in 0..3 {
+            assert_eq!(schedule.index(), 0);
+            schedule.submitted();
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn no_frames() {
+        Schedule::new(0);
+    }
+
+    #[test]
+    fn fence_is_reset_after_acquire() {
+        let mut frame = MockFrame::new();
+        frame.image = Some(2);
+        assert_eq!(start_frame(&mut frame).unwrap(), 2);
+        assert_eq!(frame.calls, ["wait", "acquire", "reset"]);
+        assert!(!frame.signaled);
+    }
+
+    #[test]
+    fn acquire_failure_keeps_fence_signaled() {
+        let mut frame = MockFrame::new();
+        frame.image = None;
+        assert_out_of_date(start_frame(&mut frame));
+        assert_eq!(frame.calls, ["wait", "acquire"]);
+        assert!(frame.signaled);
+        // 获取失败时没有提交, 同一帧可以再次开始而不会等待
+        frame.image = Some(0);
+        assert_eq!(start_frame(&mut frame).unwrap(), 0);
+        assert!(!frame.signaled);
+    }
+
+    #[test]
+    fn acquire_failure_after_wrap_around() {
+        let mut schedule = Schedule::new(2);
+        let mut frames = vec![MockFrame::new(), MockFrame::new()];
+        for image in 0..5 {
+            let frame = &mut frames[schedule.index()];
+            frame.image = Some(image % 2);
+            assert_eq!(start_frame(frame).unwrap(), image % 2);
+            frame.complete();
+            schedule.submitted();
+        }
+        assert_eq!(schedule.index(), 1);
+        let frame = &mut frames[1];
+        frame.image = None;
+        assert_out_of_date(start_frame(frame));
+        assert_out_of_date(start_frame(frame));
+        frame.image = Some(1);
+        assert_eq!(start_frame(frame).unwrap(), 1);
+        frame.complete();
+        schedule.submitted();
+        assert_eq!(schedule.index(), 0);
+        assert!(frames.iter().all(|frame| frame.signaled));
+    }
+}
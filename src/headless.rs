@@ -0,0 +1,407 @@
+// 离屏渲染: 不创建窗口和交换链, 把场景渲染到一张图片上, 再读回内存保存为png
+
+use hal::{
+    Instance,
+    adapter::PhysicalDevice,
+    device::Device,
+};
+
+use std::mem::ManuallyDrop;
+use std::path::Path;
+
+use crate::Back;
+use crate::adapter::{select_adapter, AdapterOverride};
+use crate::depth::create_attachment;
+use crate::error::{AppError, Stage};
+use crate::memory::{Allocation, Allocator, Strategy};
+use crate::pass::PassDesc;
+use crate::quad::{Quad, COLOR_RANGE, LOGO_PNG};
+use crate::queues::Queues;
+use crate::targets::{validate_samples, RenderTargets};
+use crate::texture::{Texture, TextureOptions};
+use crate::triangle::Triangle;
+
+// 离屏渲染使用的颜色格式, 和窗口渲染时选择的srgb格式保持一致
+const FORMAT: hal::format::Format = hal::format::Format::Rgba8Srgb;
+// rgba每个像素占4个字节
+const PIXEL_STRIDE: u32 = 4;
+
+// 离屏渲染可以绘制的场景
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scene {
+    // part00中的三角形
+    Triangle,
+    // 带纹理的四边形
+    Quad,
+}
+
+// 场景对应的渲染资源
+enum Renderer {
+    Triangle(Triangle),
+    Quad(Quad),
+}
+
+impl Renderer {
+    fn render_pass(&self) -> &<Back as hal::Backend>::RenderPass {
+        match self {
+            Renderer::Triangle(triangle) => &triangle.render_pass,
+            Renderer::Quad(quad) => &quad.render_pass,
+        }
+    }
+
+    unsafe fn record<S: hal::command::Shot>(
+        &self,
+        cmd_buffer: &mut hal::command::CommandBuffer<Back, hal::Graphics, S>,
+        framebuffer: &<Back as hal::Backend>::Framebuffer,
+        viewport: &hal::pso::Viewport,
+    ) {
+        match self {
+            Renderer::Triangle(triangle) => triangle.record(cmd_buffer, framebuffer, viewport),
+            Renderer::Quad(quad) => quad.record(cmd_buffer, framebuffer, viewport),
+        }
+    }
+
+    unsafe fn destroy(self, device: &<Back as hal::Backend>::Device, allocator: &mut Allocator) {
+        match self {
+            Renderer::Triangle(triangle) => triangle.destroy(device),
+            Renderer::Quad(quad) => quad.destroy(device, allocator),
+        }
+    }
+}
+
+// 字段的顺序就是drop的顺序, 和HelloTriangleApplication相同
+pub struct Headless {
+    renderer: ManuallyDrop<Renderer>,
+    // 渲染目标, 多重采样时是解析目标
+    color_image: ManuallyDrop<<Back as hal::Backend>::Image>,
+    color_memory: ManuallyDrop<Allocation>,
+    color_view: ManuallyDrop<<Back as hal::Backend>::ImageView>,
+    // 多重采样的颜色附件, 采样数为1时为空
+    render_targets: ManuallyDrop<RenderTargets>,
+    framebuffer: ManuallyDrop<<Back as hal::Backend>::Framebuffer>,
+    // 读回缓冲区, 每一行按照row_pitch对齐
+    readback_buffer: ManuallyDrop<<Back as hal::Backend>::Buffer>,
+    readback_memory: ManuallyDrop<Allocation>,
+    row_pitch: u32,
+    extent: hal::image::Extent,
+    viewport: hal::pso::Viewport,
+    command_pool: ManuallyDrop<hal::CommandPool<Back, hal::Graphics>>,
+    allocator: ManuallyDrop<Allocator>,
+    queues: Queues,
+    device: <Back as hal::Backend>::Device,
+    adapter: hal::Adapter<Back>,
+    #[allow(unused)]
+    instance: backend::Instance,
+}
+
+impl Headless {
+    // samples是多重采样的采样数, 1表示不使用多重采样
+    pub fn init(
+        width: u32,
+        height: u32,
+        scene: Scene,
+        samples: hal::image::NumSamples,
+    ) -> Result<Self, AppError>
+    {
+        let instance = backend::Instance::create("headless", 1);
+        let adapter = select_adapter(
+            instance.enumerate_adapters(),
+            AdapterOverride::from_env().as_ref(),
+            |_| true,
+        )?;
+        // 没有surface, 支持图形能力的队列族都可以使用
+        let (device, mut queues) = Queues::open(&adapter, |_| true)?;
+        // 打开设备之后创建的资源先交给partial, 出错返回时由它销毁
+        let mut partial = PartialInit::new(&device);
+        partial.command_pool = Some(unsafe {
+            device.create_command_pool_typed(
+                &queues.graphics,
+                hal::pool::CommandPoolCreateFlags::empty(),
+            )
+        }.stage("create command pool")?);
+        let allocator = partial.allocator.get_or_insert(Allocator::new(&adapter));
+
+        let limits = adapter.physical_device.limits();
+        let samples = validate_samples(&limits, samples, false)?;
+        let pass_desc = PassDesc { format: FORMAT, depth_format: None, samples };
+
+        // 渲染完成后, 颜色附件的布局直接转换为复制源
+        let renderer = partial.renderer.get_or_insert(match scene {
+            Scene::Triangle => Renderer::Triangle(Triangle::new(
+                &device,
+                FORMAT,
+                samples,
+                hal::image::Layout::TransferSrcOptimal,
+            )?),
+            Scene::Quad => {
+                // 参考图片是在没有mip链时渲染的
+                let texture = Texture::from_memory(
+                    &adapter,
+                    &device,
+                    allocator,
+                    &mut queues,
+                    LOGO_PNG,
+                    TextureOptions { mipmaps: false, ..TextureOptions::default() },
+                )?;
+                Renderer::Quad(Quad::new(
+                    &device,
+                    allocator,
+                    &mut queues,
+                    pass_desc,
+                    hal::image::Layout::TransferSrcOptimal,
+                    texture,
+                )?)
+            }
+        });
+
+        let extent = hal::image::Extent { width, height, depth: 1 };
+
+        // 创建颜色附件, 代替交换链中的图像
+        let (_, _, ref color_view) = *partial.color.get_or_insert(create_attachment(
+            &device,
+            allocator,
+            FORMAT,
+            extent,
+            1,
+            hal::image::Usage::COLOR_ATTACHMENT | hal::image::Usage::TRANSFER_SRC,
+            hal::format::Aspects::COLOR,
+        ).stage("create color image")?);
+        let render_targets = partial
+            .render_targets
+            .get_or_insert(RenderTargets::new(&device, allocator, &pass_desc, extent)?);
+        partial.framebuffer = Some(unsafe {
+            device.create_framebuffer(
+                renderer.render_pass(),
+                render_targets.attachments(color_view),
+                extent,
+            )
+        }.stage("create framebuffer")?);
+
+        // 创建读回缓冲区, 行距的对齐方式和上传纹理时相同
+        let row_alignment_mask = limits.min_buffer_copy_pitch_alignment as u32 - 1;
+        let row_pitch = (width * PIXEL_STRIDE + row_alignment_mask) & !row_alignment_mask;
+        let mut readback_buffer = unsafe {
+            device.create_buffer(
+                (row_pitch * height) as u64,
+                hal::buffer::Usage::TRANSFER_DST,
+            )
+        }.stage("create readback buffer")?;
+        // CPU读取缓存的内存更快
+        let readback_memory = match unsafe {
+            allocator.bind_buffer(
+                &device,
+                &mut readback_buffer,
+                hal::memory::Properties::CPU_VISIBLE | hal::memory::Properties::COHERENT,
+                hal::memory::Properties::CPU_CACHED,
+                Strategy::General,
+            )
+        }.stage("allocate readback memory") {
+            Ok(memory) => memory,
+            Err(err) => {
+                unsafe {
+                    device.destroy_buffer(readback_buffer);
+                }
+                return Err(err);
+            }
+        };
+
+        let viewport = hal::pso::Viewport {
+            rect: hal::pso::Rect {
+                x: 0,
+                y: 0,
+                w: width as _,
+                h: height as _,
+            },
+            depth: 0.0..1.0,
+        };
+
+        // 全部创建成功, 从partial中取出资源
+        let command_pool = partial.command_pool.take().unwrap();
+        let allocator = partial.allocator.take().unwrap();
+        let renderer = partial.renderer.take().unwrap();
+        let (color_image, color_memory, color_view) = partial.color.take().unwrap();
+        let render_targets = partial.render_targets.take().unwrap();
+        let framebuffer = partial.framebuffer.take().unwrap();
+        drop(partial);
+
+        Ok(Self {
+            renderer: ManuallyDrop::new(renderer),
+            color_image: ManuallyDrop::new(color_image),
+            color_memory: ManuallyDrop::new(color_memory),
+            color_view: ManuallyDrop::new(color_view),
+            render_targets: ManuallyDrop::new(render_targets),
+            framebuffer: ManuallyDrop::new(framebuffer),
+            readback_buffer: ManuallyDrop::new(readback_buffer),
+            readback_memory: ManuallyDrop::new(readback_memory),
+            row_pitch,
+            extent,
+            viewport,
+            command_pool: ManuallyDrop::new(command_pool),
+            allocator: ManuallyDrop::new(allocator),
+            queues,
+            device,
+            adapter,
+            instance,
+        })
+    }
+
+    // 渲染一帧, 等待渲染完成后把结果读回内存
+    pub fn render(&mut self) -> Result<image::RgbaImage, AppError> {
+        let fence = self.device.create_fence(false).stage("create fence")?;
+        unsafe {
+            let mut cmd_buffer = self.command_pool.acquire_command_buffer::<hal::command::OneShot>();
+            cmd_buffer.begin();
+            self.renderer.record(&mut cmd_buffer, &self.framebuffer, &self.viewport);
+            // render pass结束后图像已经是TransferSrcOptimal布局, 等待颜色写入完成后再复制
+            // 多重采样时解析也是在COLOR_ATTACHMENT_OUTPUT阶段写入的
+            let image_barrier: hal::memory::Barrier<Back> = hal::memory::Barrier::Image {
+                states: (hal::image::Access::COLOR_ATTACHMENT_WRITE, hal::image::Layout::TransferSrcOptimal)
+                    ..(hal::image::Access::TRANSFER_READ, hal::image::Layout::TransferSrcOptimal),
+                target: &*self.color_image,
+                families: None,
+                range: COLOR_RANGE.clone(),
+            };
+            cmd_buffer.pipeline_barrier(
+                hal::pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT..hal::pso::PipelineStage::TRANSFER,
+                hal::memory::Dependencies::empty(),
+                &[image_barrier],
+            );
+            cmd_buffer.copy_image_to_buffer(
+                &self.color_image,
+                hal::image::Layout::TransferSrcOptimal,
+                &self.readback_buffer,
+                &[hal::command::BufferImageCopy {
+                    buffer_offset: 0,
+                    buffer_width: self.row_pitch / PIXEL_STRIDE,
+                    buffer_height: self.extent.height,
+                    image_layers: hal::image::SubresourceLayers {
+                        aspects: hal::format::Aspects::COLOR,
+                        level: 0,
+                        layers: 0..1,
+                    },
+                    image_offset: hal::image::Offset { x: 0, y: 0, z: 0 },
+                    image_extent: self.extent,
+                }],
+            );
+            cmd_buffer.finish();
+            self.queues.graphics.queues[0].submit_nosemaphores(Some(&cmd_buffer), Some(&fence));
+            let wait = self.device.wait_for_fence(&fence, !0);
+            self.device.destroy_fence(fence);
+            wait.stage("wait for rendering")?;
+            self.command_pool.reset();
+        }
+
+        // 去掉每一行末尾用于对齐的字节
+        let width = self.extent.width as usize;
+        let height = self.extent.height as usize;
+        let row_len = width * PIXEL_STRIDE as usize;
+        let mut pixels = Vec::with_capacity(row_len * height);
+        unsafe {
+            let data = self.device
+                .acquire_mapping_reader::<u8>(
+                    self.allocator.memory(&self.readback_memory),
+                    self.readback_memory.range(),
+                ).stage("map readback memory")?;
+            for y in 0..height {
+                let base = y * self.row_pitch as usize;
+                pixels.extend_from_slice(&data[base..base + row_len]);
+            }
+            self.device.release_mapping_reader(data);
+        }
+        Ok(image::RgbaImage::from_raw(self.extent.width, self.extent.height, pixels).unwrap())
+    }
+
+    // 选中的适配器的名字和类型
+    pub fn adapter_info(&self) -> &hal::AdapterInfo {
+        &self.adapter.info
+    }
+
+    // 图形, 传输和计算队列
+    pub fn queues(&mut self) -> &mut Queues {
+        &mut self.queues
+    }
+
+    // 渲染一帧并保存为png
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> Result<(), AppError> {
+        self.render()?.save(path).stage("save image")
+    }
+}
+
+impl Drop for Headless {
+    fn drop(&mut self) {
+        self.device.wait_idle().unwrap();
+        unsafe {
+            use std::ptr::read;
+
+            let mut allocator = ManuallyDrop::into_inner(read(&self.allocator));
+            ManuallyDrop::into_inner(read(&self.renderer)).destroy(&self.device, &mut allocator);
+            self.device.destroy_framebuffer(ManuallyDrop::into_inner(read(&self.framebuffer)));
+            self.device.destroy_image_view(ManuallyDrop::into_inner(read(&self.color_view)));
+            self.device.destroy_image(ManuallyDrop::into_inner(read(&self.color_image)));
+            allocator.free(&self.device, ManuallyDrop::into_inner(read(&self.color_memory)));
+            ManuallyDrop::into_inner(read(&self.render_targets)).destroy(&self.device, &mut allocator);
+            self.device.destroy_buffer(ManuallyDrop::into_inner(read(&self.readback_buffer)));
+            allocator.free(&self.device, ManuallyDrop::into_inner(read(&self.readback_memory)));
+            allocator.destroy(&self.device);
+            self.device.destroy_command_pool(
+                ManuallyDrop::into_inner(read(&self.command_pool)).into_raw()
+            );
+        }
+    }
+}
+
+// init中打开设备之后已经创建的资源, init出错返回时按照创建的相反顺序销毁
+// 全部创建成功后init把资源取出, 这时drop不再销毁任何东西
+struct PartialInit<'a> {
+    device: &'a <Back as hal::Backend>::Device,
+    command_pool: Option<hal::CommandPool<Back, hal::Graphics>>,
+    allocator: Option<Allocator>,
+    renderer: Option<Renderer>,
+    color: Option<(<Back as hal::Backend>::Image, Allocation, <Back as hal::Backend>::ImageView)>,
+    render_targets: Option<RenderTargets>,
+    framebuffer: Option<<Back as hal::Backend>::Framebuffer>,
+}
+
+impl<'a> PartialInit<'a> {
+    fn new(device: &'a <Back as hal::Backend>::Device) -> Self {
+        PartialInit {
+            device,
+            command_pool: None,
+            allocator: None,
+            renderer: None,
+            color: None,
+            render_targets: None,
+            framebuffer: None,
+        }
+    }
+}
+
+impl<'a> Drop for PartialInit<'a> {
+    fn drop(&mut self) {
+        let device = self.device;
+        unsafe {
+            // 纹理和顶点数据的上传都已经等待完成, 这里只是以防万一
+            let _ = device.wait_idle();
+            if let Some(framebuffer) = self.framebuffer.take() {
+                device.destroy_framebuffer(framebuffer);
+            }
+            if let Some(mut allocator) = self.allocator.take() {
+                if let Some(render_targets) = self.render_targets.take() {
+                    render_targets.destroy(device, &mut allocator);
+                }
+                if let Some((image, memory, view)) = self.color.take() {
+                    device.destroy_image_view(view);
+                    device.destroy_image(image);
+                    allocator.free(device, memory);
+                }
+                if let Some(renderer) = self.renderer.take() {
+                    renderer.destroy(device, &mut allocator);
+                }
+                allocator.destroy(device);
+            }
+            if let Some(command_pool) = self.command_pool.take() {
+                device.destroy_command_pool(command_pool.into_raw());
+            }
+        }
+    }
+}
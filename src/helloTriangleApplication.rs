@@ -1,44 +1,271 @@
-extern crate gfx_backend_dx12 as backend;
-extern crate gfx_hal as hal;
-extern crate winit;
-extern crate image;
-
 // 设置窗口高度和宽度
 const DIMS: hal::window::Extent2D = hal::window::Extent2D { width: 800,height: 600 };
+// 窗口标题, 运行时后面会加上帧率和帧时间
+const TITLE: &str = "first program";
+// 参与统计的帧数和更新窗口标题的间隔
+const TIMING_FRAMES: usize = 120;
+const TITLE_INTERVAL_MS: u64 = 500;
 
-#[allow(unused)]
 use hal::{
     Instance,
     adapter::PhysicalDevice,
     window::Surface,
     device::Device,
-    pso::DescriptorPool,
-    format::AsFormat,
-    window::Swapchain,
 };
 
-#[allow(unused)]
-use std::io::Read;
+use std::fmt;
+use std::mem::ManuallyDrop;
+
+use crate::Back;
+use crate::adapter::{select_adapter, AdapterOverride};
+use crate::depth::choose_depth_format;
+use crate::error::{AppError, Stage};
+use crate::frame::FrameRing;
+use crate::memory::Allocator;
+use crate::pass::PassDesc;
+use crate::quad::{Quad, COLOR_RANGE, LOGO_PNG};
+use crate::queues::Queues;
+use crate::settings::{choose_present_mode, Settings};
+use crate::targets::{validate_samples, RenderTargets};
+use crate::texture::{Texture, TextureOptions};
+use crate::timing::{duration_millis, FrameStats, FrameTimer};
+#[cfg(all(debug_assertions, feature = "hot-reload"))]
+use crate::shader::ShaderWatcher;
+
+// 运行时需要告诉用户的事件, 由main_loop的调用者决定怎样显示
+#[derive(Debug)]
+pub enum Notice {
+    // 按V键切换了垂直同步, present_mode是重建后的交换链实际使用的呈现模式
+    Vsync { on: bool, present_mode: hal::window::PresentMode },
+    // 表面不支持请求的呈现模式, 交换链退回了FIFO
+    PresentModeFallback { wanted: hal::window::PresentMode },
+    // 着色器编译或者管线创建失败, 继续使用之前的管线
+    #[cfg(all(debug_assertions, feature = "hot-reload"))]
+    ShaderReload(AppError),
+}
 
-#[allow(unused)]
+impl fmt::Display for Notice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Notice::Vsync { on, present_mode } => write!(
+                f,
+                "VSync {}, present mode {:?}",
+                if *on { "on" } else { "off" },
+                present_mode,
+            ),
+            Notice::PresentModeFallback { wanted } => {
+                write!(f, "Present mode {:?} is not supported, falling back to Fifo", wanted)
+            }
+            #[cfg(all(debug_assertions, feature = "hot-reload"))]
+            Notice::ShaderReload(err) => write!(f, "Keeping the previous pipeline: {}", err),
+        }
+    }
+}
+
+// 字段的顺序就是drop的顺序:
+// 需要手动销毁的资源放在最前面, 设备在这些资源之后, 表面在窗口之前
 pub struct HelloTriangleApplication {
-    instance: backend::Instance,
+    // 管线, 描述符, 顶点缓冲和纹理
+    quad: ManuallyDrop<Quad>,
+    // 交换链相关, 重建交换链失败时为None
+    swap_chain: Option<<Back as hal::Backend>::Swapchain>,
+    frame_images: Vec<(<Back as hal::Backend>::Image, <Back as hal::Backend>::ImageView)>,
+    framebuffers: Vec<<Back as hal::Backend>::Framebuffer>,
+    // 深度附件和多重采样的颜色附件, 和交换链的尺寸相同, 重建交换链失败时为None
+    render_targets: Option<RenderTargets>,
+    viewport: hal::pso::Viewport,
+    // 交换链的格式, 深度格式和采样数
+    pass_desc: PassDesc,
+    // 当前窗口的实际尺寸, 窗口最小化时为0
+    dims: hal::window::Extent2D,
+    // 窗口尺寸改变或者交换链失效时设置, 在下一帧之前重建交换链
+    recreate_swapchain: bool,
+    settings: Settings,
+    // 是否开启垂直同步, 按V键切换, 决定重建交换链时使用的呈现模式
+    vsync: bool,
+    // 切换垂直同步后设置, 重建交换链后发出Notice::Vsync
+    vsync_toggled: bool,
+    // 交换链实际使用的呈现模式
+    present_mode: hal::window::PresentMode,
+    // 还没有交给main_loop调用者的事件
+    notices: Vec<Notice>,
+    // 每一帧的命令和同步对象
+    frames: ManuallyDrop<FrameRing>,
+    // CPU帧时间统计, 定期显示在窗口标题中
+    frame_timer: FrameTimer,
+    title_updated: std::time::Instant,
+    // debug模式下监视quad.vert和quad.frag, 修改后重新创建管线
+    #[cfg(all(debug_assertions, feature = "hot-reload"))]
+    shader_watcher: ShaderWatcher,
+    // 所有缓冲和图片的内存都从这里分配, 在其他资源之后销毁
+    allocator: ManuallyDrop<Allocator>,
+    // 设备和窗口, 渲染使用queues中的图形队列
+    queues: Queues,
+    device: <Back as hal::Backend>::Device,
+    surface: <Back as hal::Backend>::Surface,
+    adapter: hal::Adapter<Back>,
+    window: winit::Window,
     events_loop: winit::EventsLoop,
-    adapter: hal::Adapter<backend::Backend>,
+    #[allow(unused)]
+    instance: backend::Instance,
 }
 
 impl HelloTriangleApplication {
-    pub fn init() -> Self {
+    pub fn init(settings: Settings) -> Result<Self, AppError> {
+        // 在创建任何资源之前检查设置
+        let timestamp_period = settings.gpu_timestamp_period()?;
         let instance = Self::create_instance();
-        let (events_loop, surface) = Self::create_surface(&instance); 
-        let mut adapters = instance.enumerate_adapters();
-        let adapter = adapters.remove(0);
+        let (events_loop, window, mut surface) = Self::create_surface(&instance)?;
+        let mut adapter = select_adapter(
+            instance.enumerate_adapters(),
+            AdapterOverride::from_env().as_ref(),
+            |family| surface.supports_queue_family(family),
+        )?;
+        let (device, mut queues) = Self::create_device(&adapter, &surface)?;
+        // 打开设备之后创建的资源先交给partial, 出错返回时由它销毁
+        let mut partial = PartialInit::new(&device);
+        let allocator = partial.allocator.get_or_insert(Allocator::new(&adapter));
 
-        Self {
-            instance,
-            events_loop,
-            adapter,
+        let vsync = match settings.present_mode {
+            hal::window::PresentMode::Fifo | hal::window::PresentMode::Relaxed => true,
+            hal::window::PresentMode::Mailbox | hal::window::PresentMode::Immediate => false,
+        };
+        let (swap_chain, backbuffer, format, extent, present_mode) = Self::create_swapchain(
+            &mut adapter,
+            &device,
+            &mut surface,
+            DIMS,
+            &settings,
+            settings.present_mode,
+            None,
+        )?;
+        partial.swap_chain = Some(swap_chain);
+        let depth_format = if settings.depth {
+            choose_depth_format(&adapter.physical_device)
+        } else {
+            None
+        };
+        let samples = validate_samples(
+            &adapter.physical_device.limits(),
+            settings.samples,
+            depth_format.is_some(),
+        )?;
+        let mut notices = Vec::new();
+        if present_mode != settings.present_mode {
+            notices.push(Notice::PresentModeFallback { wanted: settings.present_mode });
+        }
+        let pass_desc = PassDesc { format, depth_format, samples };
+        let texture_options = TextureOptions {
+            mipmaps: settings.mipmaps,
+            ..TextureOptions::default()
+        };
+        let texture = match settings.texture {
+            Some(ref path) => Texture::from_path(
+                &adapter,
+                &device,
+                allocator,
+                &mut queues,
+                path,
+                texture_options,
+            ).stage("load texture")?,
+            None => Texture::from_memory(
+                &adapter,
+                &device,
+                allocator,
+                &mut queues,
+                LOGO_PNG,
+                texture_options,
+            )?,
+        };
+        // 四边形的着色器只采样二维纹理, 不能使用数组纹理和立方体贴图
+        if texture.layers() > 1 {
+            unsafe { texture.destroy(&device, allocator) };
+            return Err(AppError::Setting {
+                name: "texture",
+                value: "array and cube textures cannot be drawn on the quad".to_string(),
+            });
         }
+        let quad = partial.quad.get_or_insert(Quad::new(
+            &device,
+            allocator,
+            &mut queues,
+            pass_desc,
+            hal::image::Layout::Present,
+            texture,
+        )?);
+        let render_targets = partial
+            .render_targets
+            .get_or_insert(RenderTargets::new(&device, allocator, &pass_desc, extent)?);
+        let (frame_images, framebuffers) = Self::create_framebuffers(
+            &device,
+            &quad.render_pass,
+            backbuffer,
+            format,
+            render_targets,
+            extent,
+        )?;
+        partial.frame_images = frame_images;
+        partial.framebuffers = framebuffers;
+
+        partial.frames = Some(FrameRing::new(
+            &device,
+            &queues.graphics,
+            settings.frames_in_flight,
+            timestamp_period,
+        )?);
+
+        let viewport = hal::pso::Viewport {
+            rect: hal::pso::Rect {
+                x: 0,
+                y: 0,
+                w: extent.width as _,
+                h: extent.height as _,
+            },
+            depth: 0.0..1.0,
+        };
+
+        // 全部创建成功, 从partial中取出资源
+        let allocator = partial.allocator.take().unwrap();
+        let swap_chain = partial.swap_chain.take();
+        let quad = partial.quad.take().unwrap();
+        let render_targets = partial.render_targets.take();
+        let frame_images = std::mem::replace(&mut partial.frame_images, Vec::new());
+        let framebuffers = std::mem::replace(&mut partial.framebuffers, Vec::new());
+        let frames = partial.frames.take().unwrap();
+        drop(partial);
+
+        Ok(Self {
+            quad: ManuallyDrop::new(quad),
+            swap_chain,
+            frame_images,
+            framebuffers,
+            render_targets,
+            viewport,
+            pass_desc,
+            dims: DIMS,
+            recreate_swapchain: false,
+            settings,
+            vsync,
+            vsync_toggled: false,
+            present_mode,
+            notices,
+            frames: ManuallyDrop::new(frames),
+            frame_timer: FrameTimer::new(TIMING_FRAMES),
+            title_updated: std::time::Instant::now(),
+            #[cfg(all(debug_assertions, feature = "hot-reload"))]
+            shader_watcher: ShaderWatcher::new(vec![
+                concat!(env!("CARGO_MANIFEST_DIR"), "/src/data/quad.vert").into(),
+                concat!(env!("CARGO_MANIFEST_DIR"), "/src/data/quad.frag").into(),
+            ]),
+            allocator: ManuallyDrop::new(allocator),
+            queues,
+            device,
+            surface,
+            adapter,
+            window,
+            events_loop,
+            instance,
+        })
     }
 
     // 创建实例, 实例是gfx API的接口
@@ -46,10 +273,10 @@ impl HelloTriangleApplication {
         backend::Instance::create("helloworld", 1)
     }
 
-    // 创建events_loop和surface
+    // 创建events_loop和surface, 窗口需要和surface一起保存, 否则surface会失效
     fn create_surface(
         instance: &backend::Instance
-    ) -> (winit::EventsLoop, hal::Backend::Surface) 
+    ) -> Result<(winit::EventsLoop, winit::Window, <Back as hal::Backend>::Surface), AppError>
     {
         let events_loop = winit::EventsLoop::new();
         let window = winit::WindowBuilder::new()
@@ -57,21 +284,340 @@ impl HelloTriangleApplication {
                 DIMS.width as _,
                 DIMS.height as _,
             ))
-            .with_title("first program".to_string())
-            .build(&events_loop).unwrap();
-        let mut surface = instance.create_surface(&window);
-        (events_loop, surface)
+            .with_title(TITLE.to_string())
+            .build(&events_loop)
+            .stage("create window")?;
+        let surface = instance.create_surface(&window);
+        Ok((events_loop, window, surface))
     }
 
-    // 主循环函数
-    #[allow(unused)]
-    pub fn main_loop(&mut self) {
+    // 获取逻辑设备和相关的队列族, 图形队列族支持图形能力, 且和surface兼容
+    // 如果有专用的传输和计算队列族, 也一起打开
+    fn create_device(
+        adapter: &hal::Adapter<Back>,
+        surface: &<Back as hal::Backend>::Surface,
+    ) -> Result<(<Back as hal::Backend>::Device, Queues), AppError>
+    {
+        Queues::open(adapter, |family| surface.supports_queue_family(family))
+    }
+
+    // 创建交换链, 返回交换链, backbuffer, 选中的格式, 图像尺寸和呈现模式
+    // 表面不支持present_mode时使用FIFO, 图像数量来自settings
+    // 重建交换链时, 旧的交换链通过old_swapchain传入, 可以复用其中的资源
+    fn create_swapchain(
+        adapter: &mut hal::Adapter<Back>,
+        device: &<Back as hal::Backend>::Device,
+        surface: &mut <Back as hal::Backend>::Surface,
+        dims: hal::window::Extent2D,
+        settings: &Settings,
+        present_mode: hal::window::PresentMode,
+        old_swapchain: Option<<Back as hal::Backend>::Swapchain>,
+    ) -> Result<(
+        <Back as hal::Backend>::Swapchain,
+        hal::Backbuffer<Back>,
+        hal::format::Format,
+        hal::image::Extent,
+        hal::window::PresentMode,
+    ), AppError>
+    {
+        let (caps, formats, present_modes, _composite_alpha) =
+            surface.compatibility(&mut adapter.physical_device);
+        // 从所有支持的格式中, 选择srgb格式
+        let format = formats.map_or(
+            hal::format::Format::Rgba8Srgb,
+            |formats| {
+                formats
+                    .iter()
+                    .find(|format| format.base_format().1 == hal::format::ChannelType::Srgb)
+                    .map(|format| *format)
+                    .unwrap_or(formats[0])
+            }
+        );
+        let present_mode = choose_present_mode(present_mode, &present_modes);
+        let mut swap_config = hal::window::SwapchainConfig::from_caps(&caps, format, dims);
+        swap_config.image_count = settings.validate(&caps, swap_config.image_count)?;
+        swap_config.present_mode = present_mode;
+        let extent = swap_config.extent.to_extent();
+        let (swap_chain, backbuffer) = unsafe {
+            device.create_swapchain(surface, swap_config, old_swapchain)
+        }.stage("create swapchain")?;
+        Ok((swap_chain, backbuffer, format, extent, present_mode))
+    }
+
+    // 给交换链中的每个图像创建一个imageview和帧缓冲
+    // 所有帧缓冲共用同一组深度附件和多重采样的颜色附件, 出错时销毁已经创建的imageview和帧缓冲
+    fn create_framebuffers(
+        device: &<Back as hal::Backend>::Device,
+        render_pass: &<Back as hal::Backend>::RenderPass,
+        backbuffer: hal::Backbuffer<Back>,
+        format: hal::format::Format,
+        render_targets: &RenderTargets,
+        extent: hal::image::Extent,
+    ) -> Result<(
+        Vec<(<Back as hal::Backend>::Image, <Back as hal::Backend>::ImageView)>,
+        Vec<<Back as hal::Backend>::Framebuffer>,
+    ), AppError>
+    {
+        match backbuffer {
+            hal::Backbuffer::Images(images) => {
+                let mut frame_images = Vec::with_capacity(images.len());
+                let mut framebuffers = Vec::with_capacity(images.len());
+                for image in images {
+                    let rtv = unsafe {
+                        device.create_image_view(
+                            &image,
+                            hal::image::ViewKind::D2,
+                            format,
+                            hal::format::Swizzle::NO,
+                            COLOR_RANGE.clone(),
+                        )
+                    }.stage("create swapchain image view");
+                    let rtv = match rtv {
+                        Ok(rtv) => rtv,
+                        Err(err) => {
+                            unsafe { destroy_framebuffers(device, &mut frame_images, &mut framebuffers) };
+                            return Err(err);
+                        }
+                    };
+                    let fbo = unsafe {
+                        device.create_framebuffer(render_pass, render_targets.attachments(&rtv), extent)
+                    }.stage("create framebuffer");
+                    frame_images.push((image, rtv));
+                    match fbo {
+                        Ok(fbo) => framebuffers.push(fbo),
+                        Err(err) => {
+                            unsafe { destroy_framebuffers(device, &mut frame_images, &mut framebuffers) };
+                            return Err(err);
+                        }
+                    }
+                }
+                Ok((frame_images, framebuffers))
+            }
+            hal::Backbuffer::Framebuffer(fbo) => Ok((Vec::new(), vec![fbo])),
+        }
+    }
+
+    // 重建交换链, 以及依赖交换链的image view, 帧缓冲, 深度附件, 多重采样附件和视口
+    fn recreate_swapchain(&mut self) -> Result<(), AppError> {
+        // 等待所有帧渲染完毕, 再销毁旧的帧缓冲
+        self.device.wait_idle().stage("wait for device idle")?;
+        unsafe {
+            destroy_framebuffers(&self.device, &mut self.frame_images, &mut self.framebuffers);
+            if let Some(render_targets) = self.render_targets.take() {
+                render_targets.destroy(&self.device, &mut self.allocator);
+            }
+        }
+
+        // 旧的交换链交给create_swapchain, 由它负责销毁
+        let wanted = self.wanted_present_mode();
+        let old_swapchain = self.swap_chain.take();
+        let (swap_chain, backbuffer, _, extent, present_mode) = Self::create_swapchain(
+            &mut self.adapter,
+            &self.device,
+            &mut self.surface,
+            self.dims,
+            &self.settings,
+            wanted,
+            old_swapchain,
+        )?;
+        self.swap_chain = Some(swap_chain);
+        self.present_mode = present_mode;
+        // 窗口尺寸改变时也会重建交换链, 只在切换垂直同步后通知
+        if self.vsync_toggled {
+            self.vsync_toggled = false;
+            if present_mode != wanted {
+                self.notices.push(Notice::PresentModeFallback { wanted });
+            }
+            self.notices.push(Notice::Vsync { on: self.vsync, present_mode });
+        }
+        let render_targets = RenderTargets::new(
+            &self.device,
+            &mut self.allocator,
+            &self.pass_desc,
+            extent,
+        )?;
+        // 先保存附件, 创建帧缓冲失败时由drop销毁它们
+        let render_targets = self.render_targets.get_or_insert(render_targets);
+        let (frame_images, framebuffers) = Self::create_framebuffers(
+            &self.device,
+            &self.quad.render_pass,
+            backbuffer,
+            self.pass_desc.format,
+            render_targets,
+            extent,
+        )?;
+        self.frame_images = frame_images;
+        self.framebuffers = framebuffers;
+
+        self.viewport.rect.w = extent.width as _;
+        self.viewport.rect.h = extent.height as _;
+        Ok(())
+    }
+
+    // 开启垂直同步时使用FIFO或者设置中的RELAXED,
+    // 关闭时使用设置中的MAILBOX或者IMMEDIATE, 设置中没有时使用MAILBOX
+    fn wanted_present_mode(&self) -> hal::window::PresentMode {
+        use hal::window::PresentMode;
+
+        match (self.vsync, self.settings.present_mode) {
+            (true, mode @ PresentMode::Fifo) | (true, mode @ PresentMode::Relaxed) => mode,
+            (true, _) => PresentMode::Fifo,
+            (false, mode @ PresentMode::Mailbox) | (false, mode @ PresentMode::Immediate) => mode,
+            (false, _) => PresentMode::Mailbox,
+        }
+    }
+
+    // 切换垂直同步, 在下一帧之前用新的呈现模式重建交换链
+    pub fn toggle_vsync(&mut self) {
+        self.vsync = !self.vsync;
+        self.vsync_toggled = true;
+        self.recreate_swapchain = true;
+    }
+
+    // 交换链实际使用的呈现模式
+    pub fn present_mode(&self) -> hal::window::PresentMode {
+        self.present_mode
+    }
+
+    // 着色器文件修改后重新编译, 并重新创建管线
+    // 失败时发出Notice::ShaderReload, 继续使用之前的管线
+    #[cfg(all(debug_assertions, feature = "hot-reload"))]
+    fn reload_shaders(&mut self) -> Result<(), AppError> {
+        let spirv = match self.shader_watcher.poll() {
+            None => return Ok(()),
+            Some(Ok(spirv)) => spirv,
+            Some(Err(err)) => {
+                self.notices.push(Notice::ShaderReload(err));
+                return Ok(());
+            }
+        };
+        // 等待所有正在渲染的帧完成, 之后才能销毁旧的管线
+        self.frames.wait_all(&self.device)?;
+        unsafe {
+            if let Err(err) = self.quad.reload_pipeline(&self.device, &spirv[0], &spirv[1]) {
+                self.notices.push(Notice::ShaderReload(err));
+            }
+        }
+        Ok(())
+    }
+
+    // 渲染一帧
+    pub fn draw_frame(&mut self) -> Result<(), AppError> {
+        // 窗口最小化时无法创建交换链, 跳过这一帧
+        if self.dims.width == 0 || self.dims.height == 0 {
+            return Ok(());
+        }
+        let recreated = self.recreate_swapchain;
+        if self.recreate_swapchain {
+            self.recreate_swapchain().stage("recreate swapchain")?;
+            self.recreate_swapchain = false;
+        }
+
+        // 获取即将渲染的交换链图像
+        // 交换链已经过期时, 在下一帧重建交换链, 其他错误无法恢复
+        let swap_chain = self.swap_chain.as_mut().unwrap();
+        let swap_image = match unsafe { self.frames.begin_frame(&self.device, swap_chain) } {
+            Ok(i) => i,
+            Err(AppError::Acquire(hal::AcquireError::OutOfDate)) => {
+                self.recreate_swapchain = true;
+                return Ok(());
+            }
+            Err(err) => return Err(err).stage("begin frame"),
+        };
+
+        unsafe {
+            // GPU时间只包括render pass本身
+            self.quad.bind(self.frames.cmd_buffer(), &self.viewport);
+            self.frames.begin_timestamp();
+            self.quad.draw(
+                self.frames.cmd_buffer(),
+                &self.framebuffers[swap_image as usize],
+                &self.viewport,
+            );
+            self.frames.end_timestamp();
+            // 呈现失败时重建交换链, 刚刚重建过的交换链仍然失败说明无法恢复
+            match self.frames.end_frame(&mut self.queues.graphics.queues[0], swap_chain, swap_image) {
+                Ok(()) => (),
+                Err(AppError::Present) if !recreated => self.recreate_swapchain = true,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    // 选中的适配器的名字和类型
+    pub fn adapter_info(&self) -> &hal::AdapterInfo {
+        &self.adapter.info
+    }
+
+    // 图形, 传输和计算队列
+    pub fn queues(&mut self) -> &mut Queues {
+        &mut self.queues
+    }
+
+    // 最近若干帧的CPU帧时间
+    pub fn frame_stats(&self) -> Option<FrameStats> {
+        self.frame_timer.stats()
+    }
+
+    // 最近一次测量的GPU时间, 没有开启GPU计时时为None
+    pub fn gpu_time(&self) -> Option<std::time::Duration> {
+        self.frames.gpu_time()
+    }
+
+    // 在窗口标题中显示帧率和帧时间
+    fn update_title(&mut self) {
+        if self.title_updated.elapsed() < std::time::Duration::from_millis(TITLE_INTERVAL_MS) {
+            return;
+        }
+        self.title_updated = std::time::Instant::now();
+        let stats = match self.frame_stats() {
+            Some(stats) => stats,
+            None => return,
+        };
+        let mut title = format!(
+            "{} - {:.1} fps, {:.2} ms (min {:.2}, max {:.2})",
+            TITLE,
+            stats.fps(),
+            duration_millis(stats.average),
+            duration_millis(stats.min),
+            duration_millis(stats.max),
+        );
+        if let Some(gpu_time) = self.gpu_time() {
+            title += &format!(", gpu {:.2} ms", duration_millis(gpu_time));
+        }
+        self.window.set_title(&title);
+    }
+
+    // 显存的使用情况
+    pub fn memory_stats(&self) -> crate::memory::Stats {
+        self.allocator.stats()
+    }
+
+    // 主循环函数, 每一帧之前把新的事件交给notify
+    pub fn main_loop<F: FnMut(Notice)>(&mut self, mut notify: F) -> Result<(), AppError> {
         let mut running = true;
         while running {
+            for notice in self.notices.drain(..) {
+                notify(notice);
+            }
+            let mut resized = None;
+            let mut toggle_vsync = false;
             self.events_loop.poll_events(|event| {
                 if let winit::Event::WindowEvent {event, ..} = event {
                     #[allow(unused_variables)]
                     match event {
+                        winit::WindowEvent::Resized(size) => resized = Some(size),
+                        // 按V键切换垂直同步
+                        winit::WindowEvent::KeyboardInput {
+                            input:
+                                winit::KeyboardInput {
+                                    state: winit::ElementState::Pressed,
+                                    virtual_keycode: Some(winit::VirtualKeyCode::V),
+                                    ..
+                                },
+                            ..
+                        } => toggle_vsync = true,
                         winit::WindowEvent::KeyboardInput {
                             input:
                                 winit::KeyboardInput {
@@ -85,12 +631,122 @@ impl HelloTriangleApplication {
                     }
                 }
             });
+            // 窗口尺寸是逻辑尺寸, 需要换算成物理像素
+            if let Some(size) = resized {
+                let size = size.to_physical(self.window.get_hidpi_factor());
+                self.dims = hal::window::Extent2D {
+                    width: size.width as u32,
+                    height: size.height as u32,
+                };
+                self.recreate_swapchain = true;
+            }
+            if toggle_vsync {
+                self.toggle_vsync();
+            }
+            #[cfg(all(debug_assertions, feature = "hot-reload"))]
+            self.reload_shaders()?;
+            if running {
+                self.draw_frame()?;
+                // 窗口最小化时不渲染, 暂停计时
+                if self.dims.width == 0 || self.dims.height == 0 {
+                    self.frame_timer.pause();
+                } else {
+                    self.frame_timer.tick();
+                    self.update_title();
+                }
+            }
         }
+        Ok(())
     }
+}
 
-    // 清理函数
-    #[allow(unused)]
-    pub fn cleanup() {
+// 清理函数, 等待设备空闲后销毁所有资源
+impl Drop for HelloTriangleApplication {
+    fn drop(&mut self) {
+        self.device.wait_idle().unwrap();
+        unsafe {
+            use std::ptr::read;
+
+            let mut allocator = ManuallyDrop::into_inner(read(&self.allocator));
+            ManuallyDrop::into_inner(read(&self.quad)).destroy(&self.device, &mut allocator);
+            ManuallyDrop::into_inner(read(&self.frames)).destroy(&self.device);
+            destroy_framebuffers(&self.device, &mut self.frame_images, &mut self.framebuffers);
+            if let Some(render_targets) = self.render_targets.take() {
+                render_targets.destroy(&self.device, &mut allocator);
+            }
+
+            if let Some(swap_chain) = self.swap_chain.take() {
+                self.device.destroy_swapchain(swap_chain);
+            }
+            allocator.destroy(&self.device);
+        }
+    }
+}
+
+// 销毁帧缓冲和交换链图像的imageview, 交换链图像本身属于交换链
+unsafe fn destroy_framebuffers(
+    device: &<Back as hal::Backend>::Device,
+    frame_images: &mut Vec<(<Back as hal::Backend>::Image, <Back as hal::Backend>::ImageView)>,
+    framebuffers: &mut Vec<<Back as hal::Backend>::Framebuffer>,
+) {
+    for framebuffer in framebuffers.drain(..) {
+        device.destroy_framebuffer(framebuffer);
+    }
+    for (_, rtv) in frame_images.drain(..) {
+        device.destroy_image_view(rtv);
+    }
+}
 
+// init中打开设备之后已经创建的资源, init出错返回时按照创建的相反顺序销毁
+// 全部创建成功后init把资源取出, 这时drop不再销毁任何东西
+struct PartialInit<'a> {
+    device: &'a <Back as hal::Backend>::Device,
+    allocator: Option<Allocator>,
+    swap_chain: Option<<Back as hal::Backend>::Swapchain>,
+    quad: Option<Quad>,
+    render_targets: Option<RenderTargets>,
+    frame_images: Vec<(<Back as hal::Backend>::Image, <Back as hal::Backend>::ImageView)>,
+    framebuffers: Vec<<Back as hal::Backend>::Framebuffer>,
+    frames: Option<FrameRing>,
+}
+
+impl<'a> PartialInit<'a> {
+    fn new(device: &'a <Back as hal::Backend>::Device) -> Self {
+        PartialInit {
+            device,
+            allocator: None,
+            swap_chain: None,
+            quad: None,
+            render_targets: None,
+            frame_images: Vec::new(),
+            framebuffers: Vec::new(),
+            frames: None,
+        }
     }
-}
\ No newline at end of file
+}
+
+impl<'a> Drop for PartialInit<'a> {
+    fn drop(&mut self) {
+        let device = self.device;
+        unsafe {
+            // 纹理和顶点数据的上传都已经等待完成, 这里只是以防万一
+            let _ = device.wait_idle();
+            if let Some(frames) = self.frames.take() {
+                frames.destroy(device);
+            }
+            destroy_framebuffers(device, &mut self.frame_images, &mut self.framebuffers);
+            if let Some(mut allocator) = self.allocator.take() {
+                if let Some(render_targets) = self.render_targets.take() {
+                    render_targets.destroy(device, &mut allocator);
+                }
+                if let Some(quad) = self.quad.take() {
+                    quad.destroy(device, &mut allocator);
+                }
+                allocator.destroy(device);
+            }
+            if let Some(swap_chain) = self.swap_chain.take() {
+                device.destroy_swapchain(swap_chain);
+            }
+        }
+    }
+}
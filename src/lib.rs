@@ -0,0 +1,74 @@
+// 根据cargo feature选择后端, 默认情况下windows使用dx12, 其他平台使用vulkan
+// empty后端需要手动指定, 指定后优先使用
+#[cfg(feature = "empty")]
+extern crate gfx_backend_empty as backend;
+#[cfg(all(not(feature = "empty"), windows, feature = "dx12"))]
+extern crate gfx_backend_dx12 as backend;
+#[cfg(all(not(feature = "empty"), not(all(windows, feature = "dx12")), feature = "vulkan"))]
+extern crate gfx_backend_vulkan as backend;
+
+#[cfg(not(any(feature = "vulkan", feature = "dx12", feature = "empty")))]
+compile_error!("需要选择一个后端: 请启用 `vulkan`, `dx12` 或 `empty` feature 之一");
+#[cfg(all(not(windows), feature = "dx12", not(any(feature = "vulkan", feature = "empty"))))]
+compile_error!("dx12后端只能在windows上使用, 请改用 `vulkan` 或 `empty` feature");
+
+extern crate gfx_hal as hal;
+extern crate winit;
+extern crate image;
+extern crate gfx_test_derive;
+
+// 派生宏生成的代码使用::gfx_test路径, 在这个crate内部也需要能够找到它
+extern crate self as gfx_test;
+
+// 顶点格式只依赖hal, 所有后端下都可以使用
+pub mod vertex;
+
+// empty后端没有窗口和表面, 无法渲染, 以下模块只在其他后端下编译
+#[cfg(not(feature = "empty"))]
+type Back = backend::Backend;
+
+#[cfg(not(feature = "empty"))]
+pub mod adapter;
+#[cfg(not(feature = "empty"))]
+pub mod container;
+#[cfg(not(feature = "empty"))]
+mod decompress;
+#[cfg(not(feature = "empty"))]
+pub mod depth;
+#[cfg(not(feature = "empty"))]
+pub mod error;
+#[cfg(not(feature = "empty"))]
+pub mod frame;
+#[cfg(not(feature = "empty"))]
+pub mod memory;
+#[cfg(not(feature = "empty"))]
+mod mesh;
+#[cfg(not(feature = "empty"))]
+mod mipmap;
+#[cfg(not(feature = "empty"))]
+mod pass;
+#[cfg(not(feature = "empty"))]
+mod quad;
+#[cfg(not(feature = "empty"))]
+pub mod queues;
+#[cfg(not(feature = "empty"))]
+mod reflect;
+#[cfg(not(feature = "empty"))]
+pub mod settings;
+#[cfg(not(feature = "empty"))]
+mod shader;
+#[cfg(not(feature = "empty"))]
+pub mod targets;
+#[cfg(not(feature = "empty"))]
+pub mod texture;
+#[cfg(not(feature = "empty"))]
+pub mod timing;
+#[cfg(not(feature = "empty"))]
+mod triangle;
+#[cfg(not(feature = "empty"))]
+mod upload;
+#[cfg(not(feature = "empty"))]
+#[allow(non_snake_case)]
+pub mod helloTriangleApplication;
+#[cfg(not(feature = "empty"))]
+pub mod headless;
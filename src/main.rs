@@ -1,6 +1,59 @@
-mod helloTriangleApplication;
+extern crate gfx_test;
 
+// 离屏渲染时的图片尺寸, 和窗口的默认尺寸相同
+#[cfg(not(feature = "empty"))]
+const HEADLESS_DIMS: (u32, u32) = (800, 600);
+
+#[cfg(not(feature = "empty"))]
+fn main() {
+    // 出错时打印失败的步骤和原因
+    if let Err(err) = run() {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(not(feature = "empty"))]
+fn run() -> Result<(), gfx_test::error::AppError> {
+    // 使用 `--headless <path>` 参数时不创建窗口, 渲染一帧后保存为png
+    // 两种模式都可以用 `--adapter <序号或名字>` 指定适配器, 用 `--msaa <采样数>` 开启多重采样
+    // 窗口模式的其他参数见settings模块, 运行时按V键切换垂直同步
+    let args: Vec<String> = std::env::args().collect();
+    let settings = gfx_test::settings::Settings::from_env()?;
+    if let Some(pos) = args.iter().position(|arg| arg == "--headless") {
+        let path = args.get(pos + 1).map(|s| s.as_str()).unwrap_or("headless.png");
+        let mut headless = gfx_test::headless::Headless::init(
+            HEADLESS_DIMS.0,
+            HEADLESS_DIMS.1,
+            gfx_test::headless::Scene::Quad,
+            settings.samples,
+        )?;
+        let info = headless.adapter_info();
+        println!("Using adapter {} ({:?})", info.name, info.device_type);
+        print_queues(headless.queues());
+        return headless.save(path);
+    }
+
+    let mut app = gfx_test::helloTriangleApplication::HelloTriangleApplication::init(settings)?;
+    let info = app.adapter_info();
+    println!("Using adapter {} ({:?})", info.name, info.device_type);
+    print_queues(app.queues());
+    app.main_loop(|notice| println!("{}", notice))
+}
+
+// 打印打开的队列族, 没有专用的传输或计算队列族时显示None
+#[cfg(not(feature = "empty"))]
+fn print_queues(queues: &gfx_test::queues::Queues) {
+    println!(
+        "Queue families: graphics {:?}, transfer {:?}, compute {:?}",
+        queues.graphics.family(),
+        queues.transfer.as_ref().map(|group| group.family()),
+        queues.compute.as_ref().map(|group| group.family()),
+    );
+}
+
+// empty后端没有窗口和表面, 无法渲染
+#[cfg(feature = "empty")]
 fn main() {
-    let mut app = helloTriangleApplication::HelloTriangleApplication::init();
-    app.main_loop();
-}
\ No newline at end of file
+    println!("empty后端无法创建表面, 请使用 `vulkan` 或 `dx12` feature 运行");
+}
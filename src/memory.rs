@@ -0,0 +1,564 @@
+// 内存分配器: 每种内存类型从驱动分配较大的内存块, 资源从块中分出一段使用
+// 这样资源数量很多时也不会超过驱动的分配次数限制(max_memory_allocation_count)
+//
+// 两种分配策略:
+// - Linear: 在块的末尾顺序分配, 不能单独释放, 调用reset_linear后一次性回收, 适合每帧的临时数据
+// - General: 在已使用的区间之间查找第一个足够大的空隙, 可以单独释放
+
+use hal::{
+    adapter::PhysicalDevice,
+    device::Device,
+};
+
+use std::fmt;
+use std::ops::Range;
+
+use crate::Back;
+
+// 默认的内存块大小, 超过这个大小的资源单独使用一个块
+const BLOCK_SIZE: u64 = 64 * 1024 * 1024;
+// hal没有提供设备的buffer_image_granularity, 使用Vulkan规范允许的最大值128KiB,
+// 对任何设备都足够保守, 代价是不同种类的资源相邻时会多留出一些空隙
+const GRANULARITY: u64 = 0x2_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    Linear,
+    General,
+}
+
+// 资源的种类
+// 缓冲(以及线性排列的图片)和最优排列的图片在同一个内存块中相邻时,
+// 两者之间需要按照buffer_image_granularity对齐, 不能落在同一页中
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Linear,
+    Optimal,
+}
+
+#[derive(Debug)]
+pub enum MemoryError {
+    // 请求的大小为0, Vulkan不允许这样的资源
+    ZeroSize,
+    // 没有同时满足type_mask和required的内存类型
+    NoSuitableType {
+        type_mask: u64,
+        required: hal::memory::Properties,
+        // 设备上所有内存类型的属性, 下标就是MemoryTypeId
+        available: Vec<hal::memory::Properties>,
+    },
+    // 驱动分配内存块失败
+    Allocation(hal::device::AllocationError),
+    // 把资源绑定到分配的内存失败
+    Bind(hal::device::BindError),
+}
+
+impl From<hal::device::AllocationError> for MemoryError {
+    fn from(err: hal::device::AllocationError) -> Self {
+        MemoryError::Allocation(err)
+    }
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemoryError::ZeroSize => write!(f, "cannot allocate 0 bytes"),
+            MemoryError::NoSuitableType { type_mask, required, available } => {
+                write!(f, "no memory type allowed by mask {:#b} has {:?}; available types:", type_mask, required)?;
+                for (id, properties) in available.iter().enumerate() {
+                    let allowed = if type_mask & (1 << id) != 0 { "allowed" } else { "not allowed" };
+                    write!(f, "\n  {}: {:?} ({})", id, properties, allowed)?;
+                }
+                Ok(())
+            }
+            MemoryError::Allocation(err) => write!(f, "cannot allocate memory block: {:?}", err),
+            MemoryError::Bind(err) => write!(f, "cannot bind memory: {:?}", err),
+        }
+    }
+}
+
+impl std::error::Error for MemoryError {}
+
+// 在type_mask允许的内存类型中, 选择包含required全部属性, 且包含preferred中属性最多的一个
+// 例如上传缓冲需要CPU_VISIBLE | COHERENT, 纹理和顶点缓冲最好是DEVICE_LOCAL
+pub fn find_memory_type(
+    memory_types: &[hal::MemoryType],
+    type_mask: u64,
+    required: hal::memory::Properties,
+    preferred: hal::memory::Properties,
+) -> Result<hal::MemoryTypeId, MemoryError>
+{
+    memory_types
+        .iter()
+        .enumerate()
+        .filter(|(id, memory_type)| {
+            type_mask & (1 << id) != 0 && memory_type.properties.contains(required)
+        })
+        // 属性相同时选择下标较小的类型, 驱动通常把更合适的类型排在前面
+        .max_by_key(|(id, memory_type)| {
+            ((memory_type.properties & preferred).bits().count_ones(), std::cmp::Reverse(*id))
+        })
+        .map(|(id, _)| hal::MemoryTypeId(id))
+        .ok_or_else(|| MemoryError::NoSuitableType {
+            type_mask,
+            required,
+            available: memory_types.iter().map(|memory_type| memory_type.properties).collect(),
+        })
+}
+
+// 一次分配的结果, 需要通过分配它的Allocator释放
+#[derive(Debug)]
+pub struct Allocation {
+    memory_type: usize,
+    strategy: Strategy,
+    block: usize,
+    range: Range<u64>,
+}
+
+impl Allocation {
+    // 在内存块中的偏移量, 绑定资源和映射内存时使用
+    pub fn offset(&self) -> u64 {
+        self.range.start
+    }
+
+    pub fn size(&self) -> u64 {
+        self.range.end - self.range.start
+    }
+
+    // 在内存块中占用的范围
+    pub fn range(&self) -> Range<u64> {
+        self.range.clone()
+    }
+}
+
+// 使用情况统计
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    // 内存块的数量, 即allocate_memory的调用次数
+    pub blocks: usize,
+    // 从驱动分配的字节数
+    pub reserved: u64,
+    // 分配给资源的字节数, 不包括对齐产生的空隙
+    pub used: u64,
+    // 资源的数量
+    pub allocations: usize,
+}
+
+impl std::ops::AddAssign for Stats {
+    fn add_assign(&mut self, other: Stats) {
+        self.blocks += other.blocks;
+        self.reserved += other.reserved;
+        self.used += other.used;
+        self.allocations += other.allocations;
+    }
+}
+
+// 内存块, 区间的管理不依赖设备, 测试中memory为()
+struct Block<M = <Back as hal::Backend>::Memory> {
+    memory: M,
+    size: u64,
+    // 已经使用的区间, 按照起始位置排序, 每个区间的长度都大于0
+    used: Vec<(Range<u64>, Kind)>,
+}
+
+impl<M> Block<M> {
+    // 查找可以放下资源的位置, 返回(插入used的下标, 偏移量)
+    // 线性分配只考虑最后一个区间之后的空间
+    fn place(
+        &self,
+        size: u64,
+        alignment: u64,
+        kind: Kind,
+        granularity: u64,
+        strategy: Strategy,
+    ) -> Option<(usize, u64)>
+    {
+        let first = match strategy {
+            Strategy::Linear => self.used.len(),
+            Strategy::General => 0,
+        };
+        for index in first..=self.used.len() {
+            let prev = if index == 0 { None } else { self.used.get(index - 1) };
+            let next = self.used.get(index);
+            let gap_end = next.map_or(self.size, |(range, _)| range.start);
+
+            let mut offset = align(prev.map_or(0, |(range, _)| range.end), alignment);
+            if let Some((range, prev_kind)) = prev {
+                if *prev_kind != kind && same_page(range.end - 1, offset, granularity) {
+                    offset = align(offset, granularity);
+                }
+            }
+            let end = offset + size;
+            if end > gap_end {
+                continue;
+            }
+            if let Some((range, next_kind)) = next {
+                if *next_kind != kind && same_page(end - 1, range.start, granularity) {
+                    continue;
+                }
+            }
+            return Some((index, offset));
+        }
+        None
+    }
+
+    // 在place返回的位置记录一个区间
+    fn insert(&mut self, position: usize, range: Range<u64>, kind: Kind) {
+        self.used.insert(position, (range, kind));
+    }
+
+    // 删除从start开始的区间, 相邻的空隙自然合并成一个
+    fn remove(&mut self, start: u64) {
+        let index = self.used
+            .iter()
+            .position(|(range, _)| range.start == start)
+            .expect("Allocation does not belong to this allocator");
+        self.used.remove(index);
+    }
+
+    // 回收所有区间, 线性分配重新从块的开头开始
+    fn reset(&mut self) {
+        self.used.clear();
+    }
+
+    fn stats(&self) -> Stats {
+        Stats {
+            blocks: 1,
+            reserved: self.size,
+            used: self.used.iter().map(|(range, _)| range.end - range.start).sum(),
+            allocations: self.used.len(),
+        }
+    }
+}
+
+// 每种内存类型的内存块, 释放的块留下None, 保证Allocation中的下标不变
+#[derive(Default)]
+struct Pool {
+    linear: Vec<Option<Block>>,
+    general: Vec<Option<Block>>,
+}
+
+impl Pool {
+    fn blocks(&mut self, strategy: Strategy) -> &mut Vec<Option<Block>> {
+        match strategy {
+            Strategy::Linear => &mut self.linear,
+            Strategy::General => &mut self.general,
+        }
+    }
+}
+
+pub struct Allocator {
+    memory_types: Vec<hal::MemoryType>,
+    pools: Vec<Pool>,
+    // 每种内存类型的块大小, 不超过所在堆大小的1/8
+    block_sizes: Vec<u64>,
+}
+
+impl Allocator {
+    pub fn new(adapter: &hal::Adapter<Back>) -> Self {
+        let properties = adapter.physical_device.memory_properties();
+        let block_sizes = properties.memory_types
+            .iter()
+            .map(|memory_type| BLOCK_SIZE.min(properties.memory_heaps[memory_type.heap_index] / 8))
+            .collect();
+        Self {
+            pools: properties.memory_types.iter().map(|_| Pool::default()).collect(),
+            memory_types: properties.memory_types,
+            block_sizes,
+        }
+    }
+
+    // 分配满足requirements的内存, 内存类型由find_memory_type选择, 没有空间时分配新的内存块
+    pub fn allocate(
+        &mut self,
+        device: &<Back as hal::Backend>::Device,
+        requirements: &hal::memory::Requirements,
+        required: hal::memory::Properties,
+        preferred: hal::memory::Properties,
+        kind: Kind,
+        strategy: Strategy,
+    ) -> Result<Allocation, MemoryError>
+    {
+        if requirements.size == 0 {
+            return Err(MemoryError::ZeroSize);
+        }
+        let memory_type = find_memory_type(&self.memory_types, requirements.type_mask, required, preferred)?;
+        let alignment = requirements.alignment.max(1);
+        let size = requirements.size;
+        let block_size = self.block_sizes[memory_type.0].max(size);
+        let blocks = self.pools[memory_type.0].blocks(strategy);
+
+        let found = blocks
+            .iter()
+            .enumerate()
+            .filter_map(|(index, block)| block.as_ref().map(|block| (index, block)))
+            .filter_map(|(index, block)| {
+                block
+                    .place(size, alignment, kind, GRANULARITY, strategy)
+                    .map(|place| (index, place))
+            })
+            .next();
+        let (block, (position, offset)) = match found {
+            Some(found) => found,
+            None => {
+                let memory = unsafe {
+                    device.allocate_memory(memory_type, block_size)
+                }?;
+                let block = Block {
+                    memory,
+                    size: block_size,
+                    used: Vec::new(),
+                };
+                let index = match blocks.iter().position(|block| block.is_none()) {
+                    Some(index) => index,
+                    None => {
+                        blocks.push(None);
+                        blocks.len() - 1
+                    }
+                };
+                blocks[index] = Some(block);
+                (index, (0, 0))
+            }
+        };
+
+        let range = offset..offset + size;
+        blocks[block].as_mut().unwrap().insert(position, range.clone(), kind);
+        Ok(Allocation {
+            memory_type: memory_type.0,
+            strategy,
+            block,
+            range,
+        })
+    }
+
+    // 分配给allocation的内存块
+    pub fn memory(&self, allocation: &Allocation) -> &<Back as hal::Backend>::Memory {
+        let pool = &self.pools[allocation.memory_type];
+        let blocks = match allocation.strategy {
+            Strategy::Linear => &pool.linear,
+            Strategy::General => &pool.general,
+        };
+        &blocks[allocation.block].as_ref().unwrap().memory
+    }
+
+    // 为缓冲分配内存并绑定
+    pub unsafe fn bind_buffer(
+        &mut self,
+        device: &<Back as hal::Backend>::Device,
+        buffer: &mut <Back as hal::Backend>::Buffer,
+        required: hal::memory::Properties,
+        preferred: hal::memory::Properties,
+        strategy: Strategy,
+    ) -> Result<Allocation, MemoryError>
+    {
+        let requirements = device.get_buffer_requirements(buffer);
+        let allocation =
+            self.allocate(device, &requirements, required, preferred, Kind::Linear, strategy)?;
+        device
+            .bind_buffer_memory(self.memory(&allocation), allocation.offset(), buffer)
+            .map_err(MemoryError::Bind)?;
+        Ok(allocation)
+    }
+
+    // 为最优排列的图片分配内存并绑定
+    pub unsafe fn bind_image(
+        &mut self,
+        device: &<Back as hal::Backend>::Device,
+        image: &mut <Back as hal::Backend>::Image,
+        required: hal::memory::Properties,
+        preferred: hal::memory::Properties,
+        strategy: Strategy,
+    ) -> Result<Allocation, MemoryError>
+    {
+        let requirements = device.get_image_requirements(image);
+        let allocation =
+            self.allocate(device, &requirements, required, preferred, Kind::Optimal, strategy)?;
+        device
+            .bind_image_memory(self.memory(&allocation), allocation.offset(), image)
+            .map_err(MemoryError::Bind)?;
+        Ok(allocation)
+    }
+
+    // 释放一次通用分配, 单独使用的大内存块在空闲后立即还给驱动
+    // 线性分配只能通过reset_linear回收, 这里直接忽略
+    pub fn free(&mut self, device: &<Back as hal::Backend>::Device, allocation: Allocation) {
+        if allocation.strategy == Strategy::Linear {
+            return;
+        }
+        let default_size = self.block_sizes[allocation.memory_type];
+        let slot = &mut self.pools[allocation.memory_type].general[allocation.block];
+        let empty = {
+            let block = slot.as_mut().unwrap();
+            block.remove(allocation.range.start);
+            block.used.is_empty() && block.size > default_size
+        };
+        if empty {
+            let block = slot.take().unwrap();
+            unsafe {
+                device.free_memory(block.memory);
+            }
+        }
+    }
+
+    // 回收所有线性分配, 调用前需要确保GPU已经不再使用它们
+    pub fn reset_linear(&mut self) {
+        for pool in &mut self.pools {
+            for block in pool.linear.iter_mut().filter_map(Option::as_mut) {
+                block.reset();
+            }
+        }
+    }
+
+    // 某一种内存类型的使用情况
+    pub fn type_stats(&self, memory_type: hal::MemoryTypeId) -> Stats {
+        let pool = &self.pools[memory_type.0];
+        let mut stats = Stats::default();
+        for block in pool.linear.iter().chain(pool.general.iter()).filter_map(Option::as_ref) {
+            stats += block.stats();
+        }
+        stats
+    }
+
+    // 所有内存类型的使用情况
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats::default();
+        for id in 0..self.pools.len() {
+            stats += self.type_stats(hal::MemoryTypeId(id));
+        }
+        stats
+    }
+
+    // 释放所有内存块, 调用前需要确保设备已经不再使用它们
+    pub unsafe fn destroy(self, device: &<Back as hal::Backend>::Device) {
+        for pool in self.pools {
+            for block in pool.linear.into_iter().chain(pool.general).flatten() {
+                device.free_memory(block.memory);
+            }
+        }
+    }
+}
+
+// 把offset向上对齐到alignment的整数倍
+fn align(offset: u64, alignment: u64) -> u64 {
+    (offset + alignment - 1) / alignment * alignment
+}
+
+// 两个位置是否在大小为granularity的同一页中, 要求a <= b
+fn same_page(a: u64, b: u64, granularity: u64) -> bool {
+    a / granularity == b / granularity
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: u64 = 1024;
+
+    fn block(size: u64) -> Block<()> {
+        Block { memory: (), size, used: Vec::new() }
+    }
+
+    // 分配并记录区间, 返回偏移量
+    fn allocate(block: &mut Block<()>, size: u64, alignment: u64, kind: Kind, strategy: Strategy) -> Option<u64> {
+        let (position, offset) = block.place(size, alignment, kind, PAGE, strategy)?;
+        block.insert(position, offset..offset + size, kind);
+        Some(offset)
+    }
+
+    #[test]
+    fn placement_and_alignment() {
+        let mut block = block(4096);
+        assert_eq!(allocate(&mut block, 100, 1, Kind::Linear, Strategy::General), Some(0));
+        assert_eq!(allocate(&mut block, 100, 64, Kind::Linear, Strategy::General), Some(128));
+        assert_eq!(allocate(&mut block, 10, 256, Kind::Linear, Strategy::General), Some(256));
+        assert_eq!(block.used.len(), 3);
+    }
+
+    #[test]
+    fn block_full() {
+        let mut block = block(256);
+        assert_eq!(allocate(&mut block, 200, 1, Kind::Linear, Strategy::General), Some(0));
+        assert_eq!(allocate(&mut block, 100, 1, Kind::Linear, Strategy::General), None);
+        assert_eq!(allocate(&mut block, 56, 1, Kind::Linear, Strategy::General), Some(200));
+        assert_eq!(allocate(&mut block, 1, 1, Kind::Linear, Strategy::General), None);
+    }
+
+    #[test]
+    fn granularity_between_kinds() {
+        let mut block = block(8 * PAGE);
+        assert_eq!(allocate(&mut block, 100, 4, Kind::Linear, Strategy::General), Some(0));
+        // 同一种资源可以在同一页中相邻
+        assert_eq!(allocate(&mut block, 100, 4, Kind::Linear, Strategy::General), Some(100));
+        // 不同种类的资源移到下一页
+        assert_eq!(allocate(&mut block, 100, 4, Kind::Optimal, Strategy::General), Some(PAGE));
+        // 已经在新的一页中时不需要额外的对齐
+        assert_eq!(allocate(&mut block, PAGE, 4, Kind::Linear, Strategy::General), Some(2 * PAGE));
+    }
+
+    #[test]
+    fn granularity_with_next_range() {
+        let mut block = block(8 * PAGE);
+        assert_eq!(allocate(&mut block, 100, 1, Kind::Optimal, Strategy::General), Some(0));
+        assert_eq!(allocate(&mut block, 100, 1, Kind::Optimal, Strategy::General), Some(100));
+        block.remove(0);
+        // 空隙的结尾和后面的Optimal区间在同一页中, 不能放Linear, 只能放到下一页
+        assert_eq!(allocate(&mut block, 50, 1, Kind::Linear, Strategy::General), Some(PAGE));
+        assert_eq!(allocate(&mut block, 50, 1, Kind::Optimal, Strategy::General), Some(0));
+    }
+
+    #[test]
+    fn free_merges_gaps() {
+        let mut block = block(400);
+        for i in 0..4 {
+            assert_eq!(allocate(&mut block, 100, 1, Kind::Linear, Strategy::General), Some(i * 100));
+        }
+        block.remove(100);
+        assert_eq!(allocate(&mut block, 150, 1, Kind::Linear, Strategy::General), None);
+        block.remove(200);
+        // 释放相邻的两段之后, 中间的空隙可以放下更大的资源
+        assert_eq!(allocate(&mut block, 150, 1, Kind::Linear, Strategy::General), Some(100));
+        assert_eq!(allocate(&mut block, 50, 1, Kind::Linear, Strategy::General), Some(250));
+        assert_eq!(block.stats().used, 400);
+    }
+
+    #[test]
+    #[should_panic(expected = "does not belong")]
+    fn free_unknown_range() {
+        let mut block = block(400);
+        allocate(&mut block, 100, 1, Kind::Linear, Strategy::General);
+        block.remove(50);
+    }
+
+    #[test]
+    fn linear_appends_and_resets() {
+        let mut block = block(400);
+        assert_eq!(allocate(&mut block, 100, 1, Kind::Linear, Strategy::Linear), Some(0));
+        assert_eq!(allocate(&mut block, 100, 1, Kind::Linear, Strategy::Linear), Some(100));
+        block.remove(0);
+        // 线性分配不会回到前面的空隙
+        assert_eq!(allocate(&mut block, 50, 1, Kind::Linear, Strategy::Linear), Some(200));
+        assert_eq!(allocate(&mut block, 200, 1, Kind::Linear, Strategy::Linear), None);
+        block.reset();
+        assert_eq!(block.stats(), Stats { blocks: 1, reserved: 400, used: 0, allocations: 0 });
+        assert_eq!(allocate(&mut block, 400, 1, Kind::Linear, Strategy::Linear), Some(0));
+    }
+
+    #[test]
+    fn memory_type_selection() {
+        use hal::memory::Properties;
+        let memory_type = |properties| hal::MemoryType { properties, heap_index: 0 };
+        let types = [
+            memory_type(Properties::DEVICE_LOCAL),
+            memory_type(Properties::CPU_VISIBLE | Properties::COHERENT),
+            memory_type(Properties::DEVICE_LOCAL | Properties::CPU_VISIBLE | Properties::COHERENT),
+        ];
+        let visible = Properties::CPU_VISIBLE | Properties::COHERENT;
+        assert_eq!(find_memory_type(&types, 0b111, visible, Properties::empty()).unwrap(), hal::MemoryTypeId(1));
+        assert_eq!(find_memory_type(&types, 0b111, visible, Properties::DEVICE_LOCAL).unwrap(), hal::MemoryTypeId(2));
+        assert_eq!(find_memory_type(&types, 0b011, Properties::empty(), Properties::DEVICE_LOCAL).unwrap(), hal::MemoryTypeId(0));
+        match find_memory_type(&types, 0b001, visible, Properties::empty()) {
+            Err(MemoryError::NoSuitableType { available, .. }) => assert_eq!(available.len(), 3),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
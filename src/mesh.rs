@@ -0,0 +1,92 @@
+// 网格: 顶点缓冲和索引缓冲, 用draw_indexed绘制
+// 共享的顶点只需要存储一次, 以后加载模型也使用这个类型
+
+use hal::device::Device;
+
+use crate::Back;
+use crate::error::AppError;
+use crate::memory::{Allocation, Allocator};
+use crate::upload::Upload;
+use crate::vertex::VertexFormat;
+
+// 索引的类型, 顶点数量少于65536时使用u16可以节省一半的空间
+pub trait Index: Copy {
+    const TYPE: hal::IndexType;
+}
+
+impl Index for u16 {
+    const TYPE: hal::IndexType = hal::IndexType::U16;
+}
+
+impl Index for u32 {
+    const TYPE: hal::IndexType = hal::IndexType::U32;
+}
+
+pub struct Mesh {
+    vertex_buffer: <Back as hal::Backend>::Buffer,
+    vertex_memory: Allocation,
+    index_buffer: <Back as hal::Backend>::Buffer,
+    index_memory: Allocation,
+    index_type: hal::IndexType,
+    index_count: u32,
+}
+
+impl Mesh {
+    // 创建顶点缓冲和索引缓冲, 数据通过upload上传, 提交并等待upload之后才能使用
+    pub fn new<V: VertexFormat, I: Index>(
+        device: &<Back as hal::Backend>::Device,
+        allocator: &mut Allocator,
+        upload: &mut Upload,
+        vertices: &[V],
+        indices: &[I],
+    ) -> Result<Self, AppError>
+    {
+        let (vertex_buffer, vertex_memory) =
+            upload.create_buffer(device, allocator, hal::buffer::Usage::VERTEX, vertices)?;
+        let (index_buffer, index_memory) =
+            match upload.create_buffer(device, allocator, hal::buffer::Usage::INDEX, indices) {
+                Ok(index) => index,
+                Err(err) => {
+                    unsafe {
+                        device.destroy_buffer(vertex_buffer);
+                        allocator.free(device, vertex_memory);
+                    }
+                    return Err(err);
+                }
+            };
+        Ok(Self {
+            vertex_buffer,
+            vertex_memory,
+            index_buffer,
+            index_memory,
+            index_type: I::TYPE,
+            index_count: indices.len() as u32,
+        })
+    }
+
+    // 绑定顶点缓冲和索引缓冲, 顶点缓冲绑定到binding 0
+    pub unsafe fn bind<S: hal::command::Shot>(
+        &self,
+        cmd_buffer: &mut hal::command::CommandBuffer<Back, hal::Graphics, S>,
+    ) {
+        cmd_buffer.bind_vertex_buffers(0, Some((&self.vertex_buffer, 0)));
+        cmd_buffer.bind_index_buffer(hal::buffer::IndexBufferView {
+            buffer: &self.index_buffer,
+            offset: 0,
+            index_type: self.index_type,
+        });
+    }
+
+    // 绘制所有索引, 调用前需要先调用bind
+    pub unsafe fn draw(&self, encoder: &mut hal::command::RenderPassInlineEncoder<Back>) {
+        encoder.draw_indexed(0..self.index_count, 0, 0..1);
+    }
+
+    // 销毁缓冲区, 调用前需要确保设备已经不再使用它们
+    pub unsafe fn destroy(self, device: &<Back as hal::Backend>::Device, allocator: &mut Allocator) {
+        device.destroy_buffer(self.vertex_buffer);
+        device.destroy_buffer(self.index_buffer);
+        allocator.free(device, self.vertex_memory);
+        allocator.free(device, self.index_memory);
+    }
+}
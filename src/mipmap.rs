@@ -0,0 +1,171 @@
+// mip链的生成
+// 格式支持blit时, 先上传第0层, 再在图形队列上用blit_image逐层缩小: 第i - 1层是源, 第i层是目标,
+// 每一层写入完成后转换为TransferSrcOptimal, 作为下一层的源, 最后所有层级一起转换为ShaderReadOnlyOptimal
+// 不支持时在CPU上用image crate缩小, 所有层级一起上传
+
+use hal::{
+    adapter::PhysicalDevice,
+    device::Device,
+};
+
+use crate::Back;
+use crate::error::{AppError, Stage};
+use crate::queues::Queues;
+
+// 完整的mip链的层数, 最后一层是1x1
+pub fn mip_levels(width: u32, height: u32) -> hal::image::Level {
+    (32 - width.max(height).max(1).leading_zeros()) as hal::image::Level
+}
+
+// 第level层的尺寸, 每一层是上一层的一半, 最小为1
+pub fn level_extent(width: u32, height: u32, level: hal::image::Level) -> (u32, u32) {
+    ((width >> level).max(1), (height >> level).max(1))
+}
+
+// format在optimal tiling下能否作为blit的源和目标, 并且支持线性过滤
+pub fn supports_blit(
+    physical_device: &<Back as hal::Backend>::PhysicalDevice,
+    format: hal::format::Format,
+) -> bool
+{
+    physical_device
+        .format_properties(Some(format))
+        .optimal_tiling
+        .contains(
+            hal::format::ImageFeature::BLIT_SRC
+                | hal::format::ImageFeature::BLIT_DST
+                | hal::format::ImageFeature::SAMPLED_LINEAR,
+        )
+}
+
+// 在CPU上生成第1层到最后一层, 每一层都从第0层缩小, 避免误差累积
+pub fn generate_cpu(base: &image::RgbaImage, levels: hal::image::Level) -> Vec<image::RgbaImage> {
+    let (width, height) = base.dimensions();
+    (1..levels)
+        .map(|level| {
+            let (level_width, level_height) = level_extent(width, height, level);
+            image::imageops::resize(base, level_width, level_height, image::FilterType::Triangle)
+        })
+        .collect()
+}
+
+// 在图形队列上生成image的第1层到最后一层, 等待生成完成后才会返回
+// 调用前第0层的布局需要是ShaderReadOnlyOptimal, 并且上传已经完成, 其余层级的内容会被覆盖
+// 完成后所有层级的布局都是ShaderReadOnlyOptimal
+pub fn generate_blit(
+    device: &<Back as hal::Backend>::Device,
+    queues: &mut Queues,
+    image: &<Back as hal::Backend>::Image,
+    width: u32,
+    height: u32,
+    levels: hal::image::Level,
+) -> Result<(), AppError>
+{
+    if levels <= 1 {
+        return Ok(());
+    }
+    let level_range = |range: std::ops::Range<hal::image::Level>| hal::image::SubresourceRange {
+        aspects: hal::format::Aspects::COLOR,
+        levels: range,
+        layers: 0..1,
+    };
+    let level_layers = |level| hal::image::SubresourceLayers {
+        aspects: hal::format::Aspects::COLOR,
+        level,
+        layers: 0..1,
+    };
+    let level_bounds = |level| {
+        let (level_width, level_height) = level_extent(width, height, level);
+        hal::image::Offset { x: 0, y: 0, z: 0 }
+            ..hal::image::Offset { x: level_width as i32, y: level_height as i32, z: 1 }
+    };
+
+    unsafe {
+        let mut cmd_pool = device
+            .create_command_pool_typed(
+                &queues.graphics,
+                hal::pool::CommandPoolCreateFlags::TRANSIENT,
+            )
+            .stage("create mipmap command pool")?;
+        let mut cmd_buffer = cmd_pool.acquire_command_buffer::<hal::command::OneShot>();
+        cmd_buffer.begin();
+
+        // 第0层作为源, 其余层级作为目标
+        let barriers: [hal::memory::Barrier<Back>; 2] = [
+            hal::memory::Barrier::Image {
+                states: (hal::image::Access::SHADER_READ, hal::image::Layout::ShaderReadOnlyOptimal)
+                    ..(hal::image::Access::TRANSFER_READ, hal::image::Layout::TransferSrcOptimal),
+                target: image,
+                families: None,
+                range: level_range(0..1),
+            },
+            hal::memory::Barrier::Image {
+                states: (hal::image::Access::empty(), hal::image::Layout::Undefined)
+                    ..(hal::image::Access::TRANSFER_WRITE, hal::image::Layout::TransferDstOptimal),
+                target: image,
+                families: None,
+                range: level_range(1..levels),
+            },
+        ];
+        cmd_buffer.pipeline_barrier(
+            hal::pso::PipelineStage::TOP_OF_PIPE..hal::pso::PipelineStage::TRANSFER,
+            hal::memory::Dependencies::empty(),
+            &barriers,
+        );
+        for level in 1..levels {
+            cmd_buffer.blit_image(
+                image,
+                hal::image::Layout::TransferSrcOptimal,
+                image,
+                hal::image::Layout::TransferDstOptimal,
+                hal::image::Filter::Linear,
+                &[hal::command::ImageBlit {
+                    src_subresource: level_layers(level - 1),
+                    src_bounds: level_bounds(level - 1),
+                    dst_subresource: level_layers(level),
+                    dst_bounds: level_bounds(level),
+                }],
+            );
+            // 这一层写入完成后才能作为下一层的源
+            let barrier: hal::memory::Barrier<Back> = hal::memory::Barrier::Image {
+                states: (hal::image::Access::TRANSFER_WRITE, hal::image::Layout::TransferDstOptimal)
+                    ..(hal::image::Access::TRANSFER_READ, hal::image::Layout::TransferSrcOptimal),
+                target: image,
+                families: None,
+                range: level_range(level..level + 1),
+            };
+            cmd_buffer.pipeline_barrier(
+                hal::pso::PipelineStage::TRANSFER..hal::pso::PipelineStage::TRANSFER,
+                hal::memory::Dependencies::empty(),
+                &[barrier],
+            );
+        }
+        let barrier: hal::memory::Barrier<Back> = hal::memory::Barrier::Image {
+            states: (hal::image::Access::TRANSFER_READ, hal::image::Layout::TransferSrcOptimal)
+                ..(hal::image::Access::SHADER_READ, hal::image::Layout::ShaderReadOnlyOptimal),
+            target: image,
+            families: None,
+            range: level_range(0..levels),
+        };
+        cmd_buffer.pipeline_barrier(
+            hal::pso::PipelineStage::TRANSFER..hal::pso::PipelineStage::FRAGMENT_SHADER,
+            hal::memory::Dependencies::empty(),
+            &[barrier],
+        );
+        cmd_buffer.finish();
+
+        let fence = match device.create_fence(false) {
+            Ok(fence) => fence,
+            Err(err) => {
+                device.destroy_command_pool(cmd_pool.into_raw());
+                return Err(err).stage("create mipmap fence");
+            }
+        };
+        queues.graphics.queues[0].submit_nosemaphores(Some(&cmd_buffer), Some(&fence));
+        let wait = device.wait_for_fence(&fence, !0);
+        device.destroy_fence(fence);
+        device.destroy_command_pool(cmd_pool.into_raw());
+        wait.stage("wait for mipmap generation")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,148 @@
+// 窗口渲染和离屏渲染共用的render pass
+
+use hal::device::Device;
+
+use crate::Back;
+use crate::error::{AppError, Stage};
+
+// 清屏颜色
+pub(crate) const CLEAR_COLOR: [f32; 4] = [0.8, 0.8, 0.8, 1.0];
+
+// render pass的附件格式和采样数, 管线和帧缓冲都需要和它一致
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PassDesc {
+    pub format: hal::format::Format,
+    // 为None时没有深度附件
+    pub depth_format: Option<hal::format::Format>,
+    // 大于1时先渲染到多重采样的附件, 再解析到format的图像中
+    pub samples: hal::image::NumSamples,
+}
+
+// 创建renderpass, 附件0是颜色附件, 有深度格式时下一个附件是深度附件
+// 采样数大于1时颜色和深度附件是多重采样的, 最后一个附件是解析目标, 它才是最终的渲染结果
+// final_layout是渲染完成后渲染结果的布局: 交换链使用Present, 离屏渲染使用TransferSrcOptimal
+pub(crate) fn create_render_pass(
+    device: &<Back as hal::Backend>::Device,
+    desc: &PassDesc,
+    final_layout: hal::image::Layout,
+) -> Result<<Back as hal::Backend>::RenderPass, AppError>
+{
+    let PassDesc { format, depth_format, samples } = *desc;
+    let multisampled = samples > 1;
+    // 多重采样的颜色附件解析之后就不再需要, 不用保存
+    let color_attachment = hal::pass::Attachment {
+        format: Some(format),
+        samples,
+        ops: hal::pass::AttachmentOps::new(
+            hal::pass::AttachmentLoadOp::Clear,
+            if multisampled {
+                hal::pass::AttachmentStoreOp::DontCare
+            } else {
+                hal::pass::AttachmentStoreOp::Store
+            },
+        ),
+        stencil_ops: hal::pass::AttachmentOps::DONT_CARE,
+        layouts: hal::image::Layout::Undefined..if multisampled {
+            hal::image::Layout::ColorAttachmentOptimal
+        } else {
+            final_layout
+        },
+    };
+    // 深度只在这个render pass中使用, 每一帧清除, 不需要保存
+    let depth_attachment = depth_format.map(|depth_format| hal::pass::Attachment {
+        format: Some(depth_format),
+        samples,
+        ops: hal::pass::AttachmentOps::new(
+            hal::pass::AttachmentLoadOp::Clear,
+            hal::pass::AttachmentStoreOp::DontCare,
+        ),
+        stencil_ops: hal::pass::AttachmentOps::DONT_CARE,
+        layouts: hal::image::Layout::Undefined..hal::image::Layout::DepthStencilAttachmentOptimal,
+    });
+    // 解析目标的内容会被完全覆盖, 不需要加载
+    let resolve_attachment = if multisampled {
+        Some(hal::pass::Attachment {
+            format: Some(format),
+            samples: 1,
+            ops: hal::pass::AttachmentOps::new(
+                hal::pass::AttachmentLoadOp::DontCare,
+                hal::pass::AttachmentStoreOp::Store,
+            ),
+            stencil_ops: hal::pass::AttachmentOps::DONT_CARE,
+            layouts: hal::image::Layout::Undefined..final_layout,
+        })
+    } else {
+        None
+    };
+    let depth_ref = (1, hal::image::Layout::DepthStencilAttachmentOptimal);
+    let resolve_ref = (
+        if depth_format.is_some() { 2 } else { 1 },
+        hal::image::Layout::ColorAttachmentOptimal,
+    );
+    let subpass = hal::pass::SubpassDesc {
+        colors: &[(0, hal::image::Layout::ColorAttachmentOptimal)],
+        depth_stencil: depth_format.map(|_| &depth_ref),
+        inputs: &[],
+        resolves: if multisampled { std::slice::from_ref(&resolve_ref) } else { &[] },
+        preserves: &[],
+    };
+    // 上一帧的深度测试完成后才能清除深度附件
+    let stages = hal::pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT
+        | hal::pso::PipelineStage::EARLY_FRAGMENT_TESTS;
+    let dependency = hal::pass::SubpassDependency {
+        passes: hal::pass::SubpassRef::External..hal::pass::SubpassRef::Pass(0),
+        stages: stages..stages,
+        accesses: hal::image::Access::empty()
+            ..(hal::image::Access::COLOR_ATTACHMENT_READ
+                | hal::image::Access::COLOR_ATTACHMENT_WRITE
+                | hal::image::Access::DEPTH_STENCIL_ATTACHMENT_READ
+                | hal::image::Access::DEPTH_STENCIL_ATTACHMENT_WRITE),
+    };
+    let attachments = Some(color_attachment)
+        .into_iter()
+        .chain(depth_attachment)
+        .chain(resolve_attachment);
+    unsafe {
+        device.create_render_pass(attachments, &[subpass], &[dependency])
+    }.stage("create render pass")
+}
+
+// 开始render pass时的清除值, 深度清除为最远处的1.0
+pub(crate) fn clear_values(depth: bool) -> Vec<hal::command::ClearValue> {
+    let mut values = vec![hal::command::ClearValue::Color(hal::command::ClearColor::Float(CLEAR_COLOR))];
+    if depth {
+        values.push(hal::command::ClearValue::DepthStencil(hal::command::ClearDepthStencil(1.0, 0)));
+    }
+    values
+}
+
+// 有深度附件时, 开启深度测试和深度写入, 距离相同时后绘制的片元覆盖先绘制的
+pub(crate) fn depth_stencil_desc(depth: bool) -> hal::pso::DepthStencilDesc {
+    hal::pso::DepthStencilDesc {
+        depth: if depth {
+            hal::pso::DepthTest::On {
+                fun: hal::pso::Comparison::LessEqual,
+                write: true,
+            }
+        } else {
+            hal::pso::DepthTest::Off
+        },
+        depth_bounds: false,
+        stencil: hal::pso::StencilTest::Off,
+    }
+}
+
+// 管线的多重采样状态, 采样数必须和render pass的颜色附件相同, 单采样时不需要设置
+pub(crate) fn multisampling(samples: hal::image::NumSamples) -> Option<hal::pso::Multisampling> {
+    if samples > 1 {
+        Some(hal::pso::Multisampling {
+            rasterization_samples: samples,
+            sample_shading: None,
+            sample_mask: !0,
+            alpha_coverage: false,
+            alpha_to_one: false,
+        })
+    } else {
+        None
+    }
+}
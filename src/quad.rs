@@ -0,0 +1,444 @@
+// 绘制带纹理的四边形所需的资源, 窗口渲染和离屏渲染共用这些资源
+
+const ENTRY_NAME: &str = "main";
+
+use hal::{
+    device::Device,
+    pso::DescriptorPool,
+};
+
+use crate::Back;
+use crate::error::{AppError, Stage};
+use crate::memory::Allocator;
+use crate::mesh::Mesh;
+use crate::pass::{self, PassDesc};
+use crate::queues::Queues;
+use crate::reflect::Reflection;
+use crate::shader;
+use crate::texture::Texture;
+use crate::upload::Upload;
+use crate::vertex::VertexFormat;
+
+// 顶点结构体, 字段的顺序就是着色器中的location
+#[repr(C)]
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Copy, VertexFormat)]
+struct Vertex {
+    a_Pos: [f32; 2],
+    a_Uv: [f32; 2],
+}
+
+// 在这里指定顶点的坐标
+const QUAD_VERTICES: [Vertex; 4] = [
+    Vertex { a_Pos: [ -0.5, 0.33 ], a_Uv: [0.0, 1.0] },
+    Vertex { a_Pos: [  0.5, 0.33 ], a_Uv: [1.0, 1.0] },
+    Vertex { a_Pos: [  0.5,-0.33 ], a_Uv: [1.0, 0.0] },
+    Vertex { a_Pos: [ -0.5,-0.33 ], a_Uv: [0.0, 0.0] },
+];
+
+// 两个三角形共用对角线上的两个顶点
+const QUAD_INDICES: [u16; 6] = [
+    0, 1, 2,
+    0, 2, 3,
+];
+
+// 默认的纹理
+pub(crate) const LOGO_PNG: &[u8] = include_bytes!("data/logo.png");
+
+pub(crate) const COLOR_RANGE: hal::image::SubresourceRange = hal::image::SubresourceRange {
+    aspects: hal::format::Aspects::COLOR,
+    levels: 0..1,
+    layers: 0..1,
+};
+
+pub struct Quad {
+    pub render_pass: <Back as hal::Backend>::RenderPass,
+    pipeline: <Back as hal::Backend>::GraphicsPipeline,
+    pipeline_layout: <Back as hal::Backend>::PipelineLayout,
+    desc_set: <Back as hal::Backend>::DescriptorSet,
+    desc_pool: <Back as hal::Backend>::DescriptorPool,
+    set_layout: <Back as hal::Backend>::DescriptorSetLayout,
+    mesh: Mesh,
+    texture: Texture,
+    // 当前着色器的反射结果, 重新加载着色器时用来检查布局是否改变
+    #[cfg(all(debug_assertions, feature = "hot-reload"))]
+    reflection: Reflection,
+    // render pass的附件格式和采样数, 重新创建管线时使用
+    pass_desc: PassDesc,
+}
+
+impl Quad {
+    // 创建描述符, 顶点缓冲, 纹理, render pass和管线
+    // 通过queues上传顶点, 上传完成后才会返回, texture由Quad负责销毁, 出错时也会被销毁
+    // 帧缓冲中需要按照pass_desc提供深度图像和多重采样的颜色图像, 见targets模块
+    pub fn new(
+        device: &<Back as hal::Backend>::Device,
+        allocator: &mut Allocator,
+        queues: &mut Queues,
+        pass_desc: PassDesc,
+        final_layout: hal::image::Layout,
+        texture: Texture,
+    ) -> Result<Self, AppError>
+    {
+        // 描述符集合布局, 推送常数和顶点属性都来自着色器的反射
+        let descriptors = Reflection::new(&[shader::QUAD_VERT, shader::QUAD_FRAG])
+            .stage("reflect quad shaders")
+            .and_then(|reflection| {
+                let descriptors = Self::create_descriptors(device, &reflection)?;
+                Ok((reflection, descriptors))
+            });
+        let (reflection, (set_layout, desc_pool, desc_set)) = match descriptors {
+            Ok(descriptors) => descriptors,
+            Err(err) => {
+                unsafe {
+                    texture.destroy(device, allocator);
+                }
+                return Err(err);
+            }
+        };
+        let mesh = match Self::upload_mesh(device, allocator, queues) {
+            Ok(mesh) => mesh,
+            Err(err) => {
+                unsafe {
+                    device.destroy_descriptor_pool(desc_pool);
+                    device.destroy_descriptor_set_layout(set_layout);
+                    texture.destroy(device, allocator);
+                }
+                return Err(err);
+            }
+        };
+        // 把纹理和采样器写入描述符集合
+        unsafe {
+            device.write_descriptor_sets(vec![
+                hal::pso::DescriptorSetWrite {
+                    set: &desc_set,
+                    binding: 0,
+                    array_offset: 0,
+                    descriptors: Some(
+                        hal::pso::Descriptor::Image(&texture.view, hal::image::Layout::Undefined)
+                    ),
+                },
+                hal::pso::DescriptorSetWrite {
+                    set: &desc_set,
+                    binding: 1,
+                    array_offset: 0,
+                    descriptors: Some(
+                        hal::pso::Descriptor::Sampler(&texture.sampler)
+                    ),
+                },
+            ]);
+        }
+
+        let (render_pass, pipeline_layout, pipeline) = match Self::create_pass_and_pipeline(
+            device,
+            &set_layout,
+            &reflection,
+            &pass_desc,
+            final_layout,
+        ) {
+            Ok(objects) => objects,
+            Err(err) => {
+                unsafe {
+                    mesh.destroy(device, allocator);
+                    device.destroy_descriptor_pool(desc_pool);
+                    device.destroy_descriptor_set_layout(set_layout);
+                    texture.destroy(device, allocator);
+                }
+                return Err(err);
+            }
+        };
+
+        Ok(Self {
+            render_pass,
+            pipeline,
+            pipeline_layout,
+            desc_set,
+            desc_pool,
+            set_layout,
+            mesh,
+            texture,
+            #[cfg(all(debug_assertions, feature = "hot-reload"))]
+            reflection,
+            pass_desc,
+        })
+    }
+
+    // 根据反射结果创建描述符集合布局, 描述符池, 并从池中分配一个描述符集合
+    fn create_descriptors(
+        device: &<Back as hal::Backend>::Device,
+        reflection: &Reflection,
+    ) -> Result<(
+        <Back as hal::Backend>::DescriptorSetLayout,
+        <Back as hal::Backend>::DescriptorPool,
+        <Back as hal::Backend>::DescriptorSet,
+    ), AppError>
+    {
+        let set_layout = unsafe {
+            device.create_descriptor_set_layout(&reflection.set_layout_bindings(0), &[])
+        }.stage("create descriptor set layout")?;
+        let mut desc_pool = match unsafe {
+            device.create_descriptor_pool(1, &reflection.descriptor_ranges(0, 1))
+        }.stage("create descriptor pool") {
+            Ok(desc_pool) => desc_pool,
+            Err(err) => {
+                unsafe {
+                    device.destroy_descriptor_set_layout(set_layout);
+                }
+                return Err(err);
+            }
+        };
+        match unsafe { desc_pool.allocate_set(&set_layout) }.stage("allocate descriptor set") {
+            Ok(desc_set) => Ok((set_layout, desc_pool, desc_set)),
+            Err(err) => {
+                unsafe {
+                    device.destroy_descriptor_pool(desc_pool);
+                    device.destroy_descriptor_set_layout(set_layout);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    // 上传四边形的顶点和索引, 上传完成后才会返回
+    fn upload_mesh(
+        device: &<Back as hal::Backend>::Device,
+        allocator: &mut Allocator,
+        queues: &mut Queues,
+    ) -> Result<Mesh, AppError>
+    {
+        let mut upload = Upload::begin(device, queues)?;
+        let mesh = match Mesh::new(device, allocator, &mut upload, &QUAD_VERTICES, &QUAD_INDICES) {
+            Ok(mesh) => mesh,
+            Err(err) => {
+                unsafe {
+                    upload.cancel(device, allocator);
+                }
+                return Err(err);
+            }
+        };
+        match upload.submit(device, allocator, queues).and_then(|pending| pending.wait(device, allocator)) {
+            Ok(()) => Ok(mesh),
+            Err(err) => {
+                unsafe {
+                    mesh.destroy(device, allocator);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    // 创建render pass, 管线布局和管线, 出错时销毁其中已经创建的对象
+    fn create_pass_and_pipeline(
+        device: &<Back as hal::Backend>::Device,
+        set_layout: &<Back as hal::Backend>::DescriptorSetLayout,
+        reflection: &Reflection,
+        pass_desc: &PassDesc,
+        final_layout: hal::image::Layout,
+    ) -> Result<(
+        <Back as hal::Backend>::RenderPass,
+        <Back as hal::Backend>::PipelineLayout,
+        <Back as hal::Backend>::GraphicsPipeline,
+    ), AppError>
+    {
+        let render_pass = pass::create_render_pass(device, pass_desc, final_layout)?;
+        let pipeline_layout = match unsafe {
+            device.create_pipeline_layout(
+                std::iter::once(set_layout),
+                &reflection.push_constant_ranges(),
+            )
+        }.stage("create pipeline layout") {
+            Ok(pipeline_layout) => pipeline_layout,
+            Err(err) => {
+                unsafe {
+                    device.destroy_render_pass(render_pass);
+                }
+                return Err(err);
+            }
+        };
+        // 着色器在编译时已经转换成了spirv
+        let pipeline = Self::create_pipeline(
+            device,
+            &render_pass,
+            &pipeline_layout,
+            shader::QUAD_VERT,
+            shader::QUAD_FRAG,
+            reflection,
+            pass_desc,
+        ).stage("create quad pipeline");
+        match pipeline {
+            Ok(pipeline) => Ok((render_pass, pipeline_layout, pipeline)),
+            Err(err) => {
+                unsafe {
+                    device.destroy_pipeline_layout(pipeline_layout);
+                    device.destroy_render_pass(render_pass);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    // 创建着色器模块和渲染管线
+    // 顶点属性来自Vertex的字段, 和着色器的输入不一致时返回错误
+    fn create_pipeline(
+        device: &<Back as hal::Backend>::Device,
+        render_pass: &<Back as hal::Backend>::RenderPass,
+        pipeline_layout: &<Back as hal::Backend>::PipelineLayout,
+        vs_spirv: &[u8],
+        fs_spirv: &[u8],
+        reflection: &Reflection,
+        pass_desc: &PassDesc,
+    ) -> Result<<Back as hal::Backend>::GraphicsPipeline, AppError>
+    {
+        let attributes = Vertex::attributes(0);
+        reflection.check_vertex_attributes(&attributes)?;
+        let vs_module = shader::create_shader_module(device, vs_spirv)?;
+        let fs_module = match shader::create_shader_module(device, fs_spirv) {
+            Ok(module) => module,
+            Err(err) => {
+                unsafe {
+                    device.destroy_shader_module(vs_module);
+                }
+                return Err(err);
+            }
+        };
+        let pipeline = {
+            let vs_entry = hal::pso::EntryPoint {
+                entry: ENTRY_NAME,
+                module: &vs_module,
+                specialization: hal::pso::Specialization {
+                    constants: &[hal::pso::SpecializationConstant {
+                        id: 0,
+                        range: 0..4,
+                    }],
+                    data: unsafe {
+                        std::mem::transmute::<&f32, &[u8; 4]>(&0.8f32)
+                    },
+                },
+            };
+            let fs_entry = hal::pso::EntryPoint {
+                entry: ENTRY_NAME,
+                module: &fs_module,
+                specialization: hal::pso::Specialization::default(),
+            };
+            let shader_entries = hal::pso::GraphicsShaderSet {
+                vertex: vs_entry,
+                hull: None,
+                domain: None,
+                geometry: None,
+                fragment: Some(fs_entry),
+            };
+            let subpass = hal::pass::Subpass {
+                index: 0,
+                main_pass: render_pass,
+            };
+            let mut pipeline_desc = hal::pso::GraphicsPipelineDesc::new(
+                shader_entries,
+                hal::Primitive::TriangleList,
+                hal::pso::Rasterizer::FILL,
+                pipeline_layout,
+                subpass,
+            );
+            pipeline_desc.blender.targets.push(hal::pso::ColorBlendDesc(
+                hal::pso::ColorMask::ALL,
+                hal::pso::BlendState::ALPHA,
+            ));
+            pipeline_desc.depth_stencil =
+                pass::depth_stencil_desc(pass_desc.depth_format.is_some());
+            pipeline_desc.multisampling = pass::multisampling(pass_desc.samples);
+            pipeline_desc.vertex_buffers.push(Vertex::buffer_desc(0));
+            pipeline_desc.attributes.extend(attributes);
+            unsafe {
+                device.create_graphics_pipeline(&pipeline_desc, None)
+            }
+        };
+        // 管线创建完成后, 着色器模块就可以销毁了
+        unsafe {
+            device.destroy_shader_module(vs_module);
+            device.destroy_shader_module(fs_module);
+        }
+        Ok(pipeline?)
+    }
+
+    // 用新的着色器重新创建管线, 创建失败时保留原来的管线
+    // 新着色器的描述符绑定和推送常数必须和原来的相同, 因为管线布局不会重新创建
+    // 调用前需要确保旧的管线已经不再被使用
+    #[cfg(all(debug_assertions, feature = "hot-reload"))]
+    pub unsafe fn reload_pipeline(
+        &mut self,
+        device: &<Back as hal::Backend>::Device,
+        vs_spirv: &[u8],
+        fs_spirv: &[u8],
+    ) -> Result<(), AppError>
+    {
+        let reflection = Reflection::new(&[vs_spirv, fs_spirv])?;
+        self.reflection.check_layout(&reflection)?;
+        let pipeline = Self::create_pipeline(
+            device,
+            &self.render_pass,
+            &self.pipeline_layout,
+            vs_spirv,
+            fs_spirv,
+            &reflection,
+            &self.pass_desc,
+        )?;
+        let old_pipeline = std::mem::replace(&mut self.pipeline, pipeline);
+        device.destroy_graphics_pipeline(old_pipeline);
+        Ok(())
+    }
+
+    // 把绘制四边形的命令记录到命令缓冲区中, 命令缓冲区的begin和finish由调用者负责
+    pub unsafe fn record<S: hal::command::Shot>(
+        &self,
+        cmd_buffer: &mut hal::command::CommandBuffer<Back, hal::Graphics, S>,
+        framebuffer: &<Back as hal::Backend>::Framebuffer,
+        viewport: &hal::pso::Viewport,
+    ) {
+        self.bind(cmd_buffer, viewport);
+        self.draw(cmd_buffer, framebuffer, viewport);
+    }
+
+    // 设置视口并绑定管线, 顶点缓冲和描述符集合
+    pub unsafe fn bind<S: hal::command::Shot>(
+        &self,
+        cmd_buffer: &mut hal::command::CommandBuffer<Back, hal::Graphics, S>,
+        viewport: &hal::pso::Viewport,
+    ) {
+        cmd_buffer.set_viewports(0, &[viewport.clone()]);
+        cmd_buffer.set_scissors(0, &[viewport.rect]);
+        cmd_buffer.bind_graphics_pipeline(&self.pipeline);
+        self.mesh.bind(cmd_buffer);
+        cmd_buffer.bind_graphics_descriptor_sets(
+            &self.pipeline_layout,
+            0,
+            Some(&self.desc_set),
+            &[],
+        );
+    }
+
+    // 记录render pass, 需要先调用bind, 返回时render pass已经结束
+    pub unsafe fn draw<S: hal::command::Shot>(
+        &self,
+        cmd_buffer: &mut hal::command::CommandBuffer<Back, hal::Graphics, S>,
+        framebuffer: &<Back as hal::Backend>::Framebuffer,
+        viewport: &hal::pso::Viewport,
+    ) {
+        let mut encoder = cmd_buffer.begin_render_pass_inline(
+            &self.render_pass,
+            framebuffer,
+            viewport.rect,
+            &pass::clear_values(self.pass_desc.depth_format.is_some()),
+        );
+        self.mesh.draw(&mut encoder);
+    }
+
+    // 销毁所有资源, 调用前需要确保设备已经不再使用它们
+    pub unsafe fn destroy(self, device: &<Back as hal::Backend>::Device, allocator: &mut Allocator) {
+        self.mesh.destroy(device, allocator);
+        device.destroy_descriptor_pool(self.desc_pool);
+        device.destroy_descriptor_set_layout(self.set_layout);
+        self.texture.destroy(device, allocator);
+        device.destroy_render_pass(self.render_pass);
+        device.destroy_graphics_pipeline(self.pipeline);
+        device.destroy_pipeline_layout(self.pipeline_layout);
+    }
+}
@@ -0,0 +1,77 @@
+// 打开逻辑设备时同时打开图形, 传输和计算队列族
+// 专用的传输队列族通常对应显卡上的DMA引擎, 上传数据时不会占用图形队列
+// 计算队列族可以和图形队列并行执行计算任务(async compute)
+// 设备没有这样的队列族时对应的字段为None, 上传改用图形队列
+
+use hal::{
+    adapter::PhysicalDevice,
+    queue::QueueFamily,
+};
+
+use crate::Back;
+use crate::error::{AppError, Stage};
+
+pub struct Queues {
+    pub graphics: hal::QueueGroup<Back, hal::Graphics>,
+    // 不支持图形能力的传输队列族
+    pub transfer: Option<hal::QueueGroup<Back, hal::Transfer>>,
+    // 不支持图形能力的计算队列族, 不会和transfer使用同一个队列族
+    pub compute: Option<hal::QueueGroup<Back, hal::Compute>>,
+}
+
+impl Queues {
+    // 打开逻辑设备, supports_family判断支持图形能力的队列族是否可用, 例如是否和surface兼容
+    pub fn open<F>(
+        adapter: &hal::Adapter<Back>,
+        supports_family: F,
+    ) -> Result<(<Back as hal::Backend>::Device, Self), AppError>
+    where
+        F: Fn(&<Back as hal::Backend>::QueueFamily) -> bool,
+    {
+        let families = &adapter.queue_families;
+        let graphics = families
+            .iter()
+            .find(|family| family.supports_graphics() && supports_family(family))
+            .ok_or(AppError::NoAdapter)?;
+        // 优先使用只支持传输的队列族, 其次是其他不支持图形能力的队列族
+        let transfer = families
+            .iter()
+            .filter(|family| !family.supports_graphics() && family.supports_transfer())
+            .min_by_key(|family| family.supports_compute());
+        let compute = families
+            .iter()
+            .filter(|family| !family.supports_graphics() && family.supports_compute())
+            .find(|family| transfer.map_or(true, |transfer| transfer.id() != family.id()));
+
+        let mut requests = vec![(graphics, &[1.0][..])];
+        requests.extend(transfer.map(|family| (family, &[1.0][..])));
+        requests.extend(compute.map(|family| (family, &[1.0][..])));
+        let mut gpu = unsafe {
+            adapter.physical_device.open(&requests)
+        }.stage("open device")?;
+
+        let queues = Queues {
+            graphics: gpu.queues.take(graphics.id()).unwrap(),
+            transfer: transfer.map(|family| gpu.queues.take(family.id()).unwrap()),
+            compute: compute.map(|family| gpu.queues.take(family.id()).unwrap()),
+        };
+        Ok((gpu.device, queues))
+    }
+
+    // 上传数据使用的队列族, 没有专用的传输队列族时使用图形队列族
+    pub fn upload_family(&self) -> hal::queue::QueueFamilyId {
+        self.transfer
+            .as_ref()
+            .map_or(self.graphics.family(), |transfer| transfer.family())
+    }
+
+    // 上传数据使用的队列
+    pub fn upload_queue(&mut self) -> &mut hal::CommandQueue<Back, hal::Transfer> {
+        match self.transfer {
+            Some(ref mut transfer) => &mut transfer.queues[0],
+            // 图形队列族总是支持传输操作, downgrade只改变类型上的能力标记, 队列还是同一个;
+            // 返回的引用借用了self, 在它被释放之前不能再把这个队列当作图形队列使用
+            None => unsafe { self.graphics.queues[0].downgrade() },
+        }
+    }
+}
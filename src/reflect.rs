@@ -0,0 +1,769 @@
+// 着色器反射: 从spirv中读取顶点输入, 描述符绑定和推送常数
+// 用来生成描述符集合布局, 描述符池的大小和推送常数的范围, 并检查顶点属性,
+// 这样它们就不需要手动和着色器保持一致了
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+
+const MAGIC: u32 = 0x0723_0203;
+// spirv头部的长度(以字为单位)
+const HEADER_LEN: usize = 5;
+
+// 用到的操作码
+const OP_NAME: u32 = 5;
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_BOOL: u32 = 20;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+// 用到的修饰
+const DECORATION_BLOCK: u32 = 2;
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_BUILT_IN: u32 = 11;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+// 用到的存储类型
+const STORAGE_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_INPUT: u32 = 1;
+const STORAGE_UNIFORM: u32 = 2;
+const STORAGE_PUSH_CONSTANT: u32 = 9;
+const STORAGE_STORAGE_BUFFER: u32 = 12;
+
+// 递归查看类型时允许的最大嵌套层数, 错误的spirv中类型可能引用自己
+const MAX_TYPE_DEPTH: u32 = 32;
+
+#[derive(Debug)]
+pub enum ReflectError {
+    // 数据不是合法的spirv
+    InvalidSpirv(&'static str),
+    // 着色器中使用了反射不支持的类型
+    Unsupported(String),
+    // 两个着色器阶段中, 同一个绑定的描述符类型不同
+    BindingMismatch {
+        set: u32,
+        binding: u32,
+        first: hal::pso::DescriptorType,
+        second: hal::pso::DescriptorType,
+    },
+    // 顶点着色器的输入和顶点结构体的字段不同, vertex为None表示结构体中没有这个location
+    AttributeMismatch {
+        location: u32,
+        shader: hal::format::Format,
+        vertex: Option<hal::format::Format>,
+    },
+    // 着色器中的绑定和已经创建的描述符集合布局不同
+    LayoutMismatch,
+}
+
+impl fmt::Display for ReflectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReflectError::InvalidSpirv(reason) => write!(f, "invalid spirv: {}", reason),
+            ReflectError::Unsupported(what) => write!(f, "unsupported shader interface: {}", what),
+            ReflectError::BindingMismatch { set, binding, first, second } => write!(
+                f,
+                "set {} binding {} is declared as {:?} in one stage and {:?} in another",
+                set, binding, first, second,
+            ),
+            ReflectError::AttributeMismatch { location, shader, vertex: Some(vertex) } => write!(
+                f,
+                "vertex input at location {} is {:?} in the shader but {:?} in the vertex struct",
+                location, shader, vertex,
+            ),
+            ReflectError::AttributeMismatch { location, shader, vertex: None } => write!(
+                f,
+                "vertex input at location {} ({:?}) has no matching field in the vertex struct",
+                location, shader,
+            ),
+            ReflectError::LayoutMismatch => write!(
+                f,
+                "shader bindings do not match the existing descriptor set layout",
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReflectError {}
+
+// 着色器中声明的一个描述符绑定
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    pub set: u32,
+    pub binding: u32,
+    pub ty: hal::pso::DescriptorType,
+    pub count: usize,
+    pub stage_flags: hal::pso::ShaderStageFlags,
+}
+
+// 一个着色器模块的反射结果
+#[derive(Debug, Clone)]
+struct ShaderInfo {
+    stage: hal::pso::ShaderStageFlags,
+    // (location, format), 按照location排序
+    inputs: Vec<(u32, hal::format::Format)>,
+    bindings: Vec<Binding>,
+    // 推送常数块的大小, 以字节为单位
+    push_constants: u32,
+}
+
+// 管线中所有着色器的反射结果
+#[derive(Debug, Clone)]
+pub struct Reflection {
+    shaders: Vec<ShaderInfo>,
+    // 合并所有阶段之后的绑定, 按照(set, binding)排序
+    bindings: Vec<Binding>,
+}
+
+impl Reflection {
+    // 反射管线中的所有着色器, 并检查不同阶段之间的绑定是否一致
+    pub fn new(modules: &[&[u8]]) -> Result<Self, ReflectError> {
+        let shaders = modules
+            .iter()
+            .map(|spirv| Module::parse(spirv).and_then(|module| module.reflect()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut bindings: Vec<Binding> = Vec::new();
+        for binding in shaders.iter().flat_map(|shader| shader.bindings.iter()) {
+            match bindings
+                .iter_mut()
+                .find(|b| b.set == binding.set && b.binding == binding.binding)
+            {
+                Some(existing) => {
+                    if existing.ty != binding.ty {
+                        return Err(ReflectError::BindingMismatch {
+                            set: binding.set,
+                            binding: binding.binding,
+                            first: existing.ty,
+                            second: binding.ty,
+                        });
+                    }
+                    existing.count = existing.count.max(binding.count);
+                    existing.stage_flags |= binding.stage_flags;
+                }
+                None => bindings.push(binding.clone()),
+            }
+        }
+        bindings.sort_by_key(|b| (b.set, b.binding));
+
+        Ok(Self { shaders, bindings })
+    }
+
+    // 检查新的着色器是否可以使用根据self创建的描述符集合布局和管线布局
+    #[cfg(any(test, all(debug_assertions, feature = "hot-reload")))]
+    pub fn check_layout(&self, other: &Reflection) -> Result<(), ReflectError> {
+        if self.bindings != other.bindings
+            || self.push_constant_ranges() != other.push_constant_ranges()
+        {
+            return Err(ReflectError::LayoutMismatch);
+        }
+        Ok(())
+    }
+
+    // 描述符集合set的布局
+    pub fn set_layout_bindings(&self, set: u32) -> Vec<hal::pso::DescriptorSetLayoutBinding> {
+        self.bindings
+            .iter()
+            .filter(|b| b.set == set)
+            .map(|b| hal::pso::DescriptorSetLayoutBinding {
+                binding: b.binding,
+                ty: b.ty,
+                count: b.count,
+                stage_flags: b.stage_flags,
+                immutable_samplers: false,
+            })
+            .collect()
+    }
+
+    // 分配sets个描述符集合set所需的描述符池大小
+    pub fn descriptor_ranges(&self, set: u32, sets: usize) -> Vec<hal::pso::DescriptorRangeDesc> {
+        let mut ranges: Vec<hal::pso::DescriptorRangeDesc> = Vec::new();
+        for b in self.bindings.iter().filter(|b| b.set == set) {
+            match ranges.iter_mut().find(|range| range.ty == b.ty) {
+                Some(range) => range.count += b.count * sets,
+                None => ranges.push(hal::pso::DescriptorRangeDesc {
+                    ty: b.ty,
+                    count: b.count * sets,
+                }),
+            }
+        }
+        ranges
+    }
+
+    // 每个阶段的推送常数范围, 范围的单位是u32
+    pub fn push_constant_ranges(&self) -> Vec<(hal::pso::ShaderStageFlags, Range<u32>)> {
+        self.shaders
+            .iter()
+            .filter(|shader| shader.push_constants > 0)
+            .map(|shader| (shader.stage, 0..(shader.push_constants + 3) / 4))
+            .collect()
+    }
+
+    // 检查顶点结构体提供的属性是否和顶点着色器的输入一致
+    // 着色器的每个输入都需要一个location和格式都相同的属性
+    pub fn check_vertex_attributes(
+        &self,
+        attributes: &[hal::pso::AttributeDesc],
+    ) -> Result<(), ReflectError>
+    {
+        let inputs = self.shaders
+            .iter()
+            .find(|shader| shader.stage == hal::pso::ShaderStageFlags::VERTEX)
+            .map_or(&[][..], |shader| &shader.inputs[..]);
+        for &(location, format) in inputs {
+            let vertex = attributes
+                .iter()
+                .find(|attribute| attribute.location == location)
+                .map(|attribute| attribute.element.format);
+            if vertex != Some(format) {
+                return Err(ReflectError::AttributeMismatch {
+                    location,
+                    shader: format,
+                    vertex,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ScalarKind {
+    Float,
+    Int,
+    Uint,
+    Bool,
+}
+
+#[derive(Debug, Clone)]
+enum Type {
+    Scalar { kind: ScalarKind, width: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, count: u32 },
+    // sampled为1表示采样图像, 为2表示存储图像
+    Image { sampled: u32 },
+    Sampler,
+    SampledImage,
+    Array { element: u32, length: u32 },
+    RuntimeArray,
+    Struct { members: Vec<u32> },
+    Pointer { pointee: u32 },
+}
+
+#[derive(Debug, Default)]
+struct Decorations {
+    location: Option<u32>,
+    binding: Option<u32>,
+    set: Option<u32>,
+    array_stride: Option<u32>,
+    built_in: bool,
+    block: bool,
+    buffer_block: bool,
+}
+
+// 解析之后的spirv模块, 只保存反射需要的信息
+#[derive(Debug, Default)]
+struct Module {
+    execution_model: Option<u32>,
+    names: HashMap<u32, String>,
+    types: HashMap<u32, Type>,
+    constants: HashMap<u32, u32>,
+    // (变量id, 类型id, 存储类型)
+    variables: Vec<(u32, u32, u32)>,
+    decorations: HashMap<u32, Decorations>,
+    member_offsets: HashMap<(u32, u32), u32>,
+}
+
+impl Module {
+    fn parse(bytes: &[u8]) -> Result<Self, ReflectError> {
+        if bytes.len() % 4 != 0 {
+            return Err(ReflectError::InvalidSpirv("length is not a multiple of 4"));
+        }
+        let words: Vec<u32> = bytes
+            .chunks(4)
+            .map(|b| u32::from(b[0]) | u32::from(b[1]) << 8 | u32::from(b[2]) << 16 | u32::from(b[3]) << 24)
+            .collect();
+        if words.len() < HEADER_LEN || words[0] != MAGIC {
+            return Err(ReflectError::InvalidSpirv("bad header"));
+        }
+
+        let mut module = Module::default();
+        let mut pos = HEADER_LEN;
+        while pos < words.len() {
+            let count = (words[pos] >> 16) as usize;
+            let opcode = words[pos] & 0xffff;
+            if count == 0 || pos + count > words.len() {
+                return Err(ReflectError::InvalidSpirv("truncated instruction"));
+            }
+            module.instruction(opcode, &words[pos + 1..pos + count]);
+            pos += count;
+        }
+        Ok(module)
+    }
+
+    fn instruction(&mut self, opcode: u32, operands: &[u32]) {
+        // 指令的操作数个数不对时直接忽略, 由驱动报告错误
+        let operand = |i: usize| operands.get(i).cloned().unwrap_or(0);
+        match opcode {
+            OP_NAME => {
+                self.names.insert(operand(0), decode_string(operands.get(1..).unwrap_or(&[])));
+            }
+            OP_ENTRY_POINT => {
+                self.execution_model.get_or_insert(operand(0));
+            }
+            OP_TYPE_BOOL => {
+                self.types.insert(operand(0), Type::Scalar { kind: ScalarKind::Bool, width: 32 });
+            }
+            OP_TYPE_INT => {
+                let kind = if operand(2) == 0 { ScalarKind::Uint } else { ScalarKind::Int };
+                self.types.insert(operand(0), Type::Scalar { kind, width: operand(1) });
+            }
+            OP_TYPE_FLOAT => {
+                self.types.insert(operand(0), Type::Scalar { kind: ScalarKind::Float, width: operand(1) });
+            }
+            OP_TYPE_VECTOR => {
+                self.types.insert(operand(0), Type::Vector { component: operand(1), count: operand(2) });
+            }
+            OP_TYPE_MATRIX => {
+                self.types.insert(operand(0), Type::Matrix { column: operand(1), count: operand(2) });
+            }
+            OP_TYPE_IMAGE => {
+                self.types.insert(operand(0), Type::Image { sampled: operand(6) });
+            }
+            OP_TYPE_SAMPLER => {
+                self.types.insert(operand(0), Type::Sampler);
+            }
+            OP_TYPE_SAMPLED_IMAGE => {
+                self.types.insert(operand(0), Type::SampledImage);
+            }
+            OP_TYPE_ARRAY => {
+                self.types.insert(operand(0), Type::Array { element: operand(1), length: operand(2) });
+            }
+            OP_TYPE_RUNTIME_ARRAY => {
+                self.types.insert(operand(0), Type::RuntimeArray);
+            }
+            OP_TYPE_STRUCT => {
+                self.types.insert(operand(0), Type::Struct { members: operands.get(1..).unwrap_or(&[]).to_vec() });
+            }
+            OP_TYPE_POINTER => {
+                self.types.insert(operand(0), Type::Pointer { pointee: operand(2) });
+            }
+            OP_CONSTANT => {
+                self.constants.insert(operand(1), operand(2));
+            }
+            OP_VARIABLE => {
+                self.variables.push((operand(1), operand(0), operand(2)));
+            }
+            OP_DECORATE => {
+                let decorations = self.decorations.entry(operand(0)).or_default();
+                match operand(1) {
+                    DECORATION_BLOCK => decorations.block = true,
+                    DECORATION_BUFFER_BLOCK => decorations.buffer_block = true,
+                    DECORATION_ARRAY_STRIDE => decorations.array_stride = Some(operand(2)),
+                    DECORATION_BUILT_IN => decorations.built_in = true,
+                    DECORATION_LOCATION => decorations.location = Some(operand(2)),
+                    DECORATION_BINDING => decorations.binding = Some(operand(2)),
+                    DECORATION_DESCRIPTOR_SET => decorations.set = Some(operand(2)),
+                    _ => (),
+                }
+            }
+            OP_MEMBER_DECORATE => {
+                if operand(2) == DECORATION_OFFSET {
+                    self.member_offsets.insert((operand(0), operand(1)), operand(3));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn reflect(&self) -> Result<ShaderInfo, ReflectError> {
+        // 执行模型的编号: 0顶点, 1细分控制, 2细分计算, 3几何, 4片段, 5计算
+        let stage = match self.execution_model {
+            Some(0) => hal::pso::ShaderStageFlags::VERTEX,
+            Some(1) => hal::pso::ShaderStageFlags::HULL,
+            Some(2) => hal::pso::ShaderStageFlags::DOMAIN,
+            Some(3) => hal::pso::ShaderStageFlags::GEOMETRY,
+            Some(4) => hal::pso::ShaderStageFlags::FRAGMENT,
+            Some(5) => hal::pso::ShaderStageFlags::COMPUTE,
+            _ => return Err(ReflectError::InvalidSpirv("missing entry point")),
+        };
+
+        let mut info = ShaderInfo {
+            stage,
+            inputs: Vec::new(),
+            bindings: Vec::new(),
+            push_constants: 0,
+        };
+        let no_decorations = Decorations::default();
+        for &(id, ty, storage) in &self.variables {
+            let decorations = self.decorations.get(&id).unwrap_or(&no_decorations);
+            let ty = match self.types.get(&ty) {
+                Some(Type::Pointer { pointee }) => *pointee,
+                _ => return Err(ReflectError::InvalidSpirv("variable is not a pointer")),
+            };
+            match storage {
+                // 内置变量(例如gl_VertexIndex)没有location, 不是顶点属性
+                STORAGE_INPUT if stage == hal::pso::ShaderStageFlags::VERTEX => {
+                    if decorations.built_in || self.is_built_in_block(ty, 0)? {
+                        continue;
+                    }
+                    let location = decorations.location.ok_or_else(|| {
+                        ReflectError::Unsupported(format!("input `{}` has no location", self.name(id)))
+                    })?;
+                    info.inputs.push((location, self.input_format(id, ty)?));
+                }
+                STORAGE_UNIFORM_CONSTANT | STORAGE_UNIFORM | STORAGE_STORAGE_BUFFER => {
+                    let (ty, count) = match self.types.get(&ty) {
+                        Some(Type::Array { element, length }) => {
+                            (*element, self.constants.get(length).cloned().unwrap_or(1) as usize)
+                        }
+                        _ => (ty, 1),
+                    };
+                    info.bindings.push(Binding {
+                        set: decorations.set.unwrap_or(0),
+                        binding: decorations.binding.unwrap_or(0),
+                        ty: self.descriptor_type(id, ty, storage)?,
+                        count,
+                        stage_flags: stage,
+                    });
+                }
+                STORAGE_PUSH_CONSTANT => {
+                    info.push_constants = info.push_constants.max(self.size_of(ty, 0)?);
+                }
+                _ => (),
+            }
+        }
+        info.inputs.sort_by_key(|&(location, _)| location);
+        Ok(info)
+    }
+
+    fn name(&self, id: u32) -> &str {
+        self.names.get(&id).map_or("<unnamed>", |name| name.as_str())
+    }
+
+    // gl_PerVertex这样的输入块的成员都是内置变量, depth是已经嵌套的层数
+    fn is_built_in_block(&self, ty: u32, depth: u32) -> Result<bool, ReflectError> {
+        if depth > MAX_TYPE_DEPTH {
+            return Err(ReflectError::InvalidSpirv("types are nested too deeply"));
+        }
+        match self.types.get(&ty) {
+            Some(Type::Struct { .. }) => {
+                Ok(self.decorations.get(&ty).map_or(false, |d| d.block))
+            }
+            Some(Type::Array { element, .. }) => self.is_built_in_block(*element, depth + 1),
+            _ => Ok(false),
+        }
+    }
+
+    // 把顶点输入的类型转换成顶点属性的格式
+    fn input_format(&self, id: u32, ty: u32) -> Result<hal::format::Format, ReflectError> {
+        use hal::format::Format;
+
+        let (component, count) = match self.types.get(&ty) {
+            Some(Type::Vector { component, count }) => (*component, *count),
+            Some(Type::Scalar { .. }) => (ty, 1),
+            _ => return Err(ReflectError::Unsupported(format!("input `{}` is not a scalar or vector", self.name(id)))),
+        };
+        let kind = match self.types.get(&component) {
+            Some(Type::Scalar { kind, width: 32 }) => *kind,
+            _ => return Err(ReflectError::Unsupported(format!("input `{}` is not 32 bits wide", self.name(id)))),
+        };
+        let format = match (kind, count) {
+            (ScalarKind::Float, 1) => Format::R32Float,
+            (ScalarKind::Float, 2) => Format::Rg32Float,
+            (ScalarKind::Float, 3) => Format::Rgb32Float,
+            (ScalarKind::Float, 4) => Format::Rgba32Float,
+            (ScalarKind::Int, 1) => Format::R32Int,
+            (ScalarKind::Int, 2) => Format::Rg32Int,
+            (ScalarKind::Int, 3) => Format::Rgb32Int,
+            (ScalarKind::Int, 4) => Format::Rgba32Int,
+            (ScalarKind::Uint, 1) => Format::R32Uint,
+            (ScalarKind::Uint, 2) => Format::Rg32Uint,
+            (ScalarKind::Uint, 3) => Format::Rgb32Uint,
+            (ScalarKind::Uint, 4) => Format::Rgba32Uint,
+            _ => return Err(ReflectError::Unsupported(format!("input `{}` has an unsupported type", self.name(id)))),
+        };
+        Ok(format)
+    }
+
+    fn descriptor_type(
+        &self,
+        id: u32,
+        ty: u32,
+        storage: u32,
+    ) -> Result<hal::pso::DescriptorType, ReflectError>
+    {
+        use hal::pso::DescriptorType;
+
+        let decorations = self.decorations.get(&ty);
+        let ty = match (storage, self.types.get(&ty)) {
+            (_, Some(Type::Sampler)) => DescriptorType::Sampler,
+            (_, Some(Type::SampledImage)) => DescriptorType::CombinedImageSampler,
+            (_, Some(Type::Image { sampled: 2 })) => DescriptorType::StorageImage,
+            (_, Some(Type::Image { .. })) => DescriptorType::SampledImage,
+            (STORAGE_STORAGE_BUFFER, Some(Type::Struct { .. })) => DescriptorType::StorageBuffer,
+            (STORAGE_UNIFORM, Some(Type::Struct { .. })) => {
+                if decorations.map_or(false, |d| d.buffer_block) {
+                    DescriptorType::StorageBuffer
+                } else {
+                    DescriptorType::UniformBuffer
+                }
+            }
+            _ => return Err(ReflectError::Unsupported(format!("binding `{}` has an unsupported type", self.name(id)))),
+        };
+        Ok(ty)
+    }
+
+    // 计算推送常数块的大小, 成员的偏移量来自Offset修饰, depth是已经嵌套的层数
+    fn size_of(&self, ty: u32, depth: u32) -> Result<u32, ReflectError> {
+        if depth > MAX_TYPE_DEPTH {
+            return Err(ReflectError::InvalidSpirv("types are nested too deeply"));
+        }
+        let overflow = || ReflectError::InvalidSpirv("type size overflows");
+        let size = match self.types.get(&ty) {
+            Some(Type::Scalar { width, .. }) => width / 8,
+            Some(Type::Vector { component, count }) => {
+                self.size_of(*component, depth + 1)?.checked_mul(*count).ok_or_else(overflow)?
+            }
+            Some(Type::Matrix { column, count }) => {
+                self.size_of(*column, depth + 1)?.checked_mul(*count).ok_or_else(overflow)?
+            }
+            Some(Type::Array { element, length }) => {
+                let length = self.constants.get(length).cloned().unwrap_or(1);
+                let stride = match self.decorations.get(&ty).and_then(|d| d.array_stride) {
+                    Some(stride) => stride,
+                    None => self.size_of(*element, depth + 1)?,
+                };
+                stride.checked_mul(length).ok_or_else(overflow)?
+            }
+            Some(Type::Struct { members }) => {
+                let mut size = 0u32;
+                for (index, &member) in members.iter().enumerate() {
+                    let offset = self.member_offsets.get(&(ty, index as u32)).cloned().unwrap_or(size);
+                    let end = offset.checked_add(self.size_of(member, depth + 1)?).ok_or_else(overflow)?;
+                    size = size.max(end);
+                }
+                size
+            }
+            _ => return Err(ReflectError::Unsupported("push constant block member".to_string())),
+        };
+        Ok(size)
+    }
+}
+
+// spirv中的字符串以0结尾, 每4个字节打包成一个字
+fn decode_string(words: &[u32]) -> String {
+    let bytes: Vec<u8> = words
+        .iter()
+        .flat_map(|word| (0..4).map(move |i| (word >> (i * 8)) as u8))
+        .take_while(|&b| b != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shader;
+    use hal::format::Format;
+    use hal::pso::{DescriptorType, ShaderStageFlags};
+
+    // 由build.rs编译的测试着色器, 见src/data/test
+    const REFLECT_VERT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/data/test/reflect.vert.spv"));
+    const REFLECT_FRAG: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/data/test/reflect.frag.spv"));
+
+    fn binding(set: u32, binding: u32, ty: DescriptorType, count: usize, stage_flags: ShaderStageFlags) -> Binding {
+        Binding { set, binding, ty, count, stage_flags }
+    }
+
+    fn attribute(location: u32, format: Format) -> hal::pso::AttributeDesc {
+        hal::pso::AttributeDesc {
+            location,
+            binding: 0,
+            element: hal::pso::Element { format, offset: 0 },
+        }
+    }
+
+    // spirv头部, 后面跟着words中的指令
+    fn spirv(words: &[u32]) -> Vec<u8> {
+        [MAGIC, 0x0001_0000, 0, 1, 0]
+            .iter()
+            .chain(words)
+            .flat_map(|word| (0..4).map(move |i| (word >> (i * 8)) as u8))
+            .collect()
+    }
+
+    #[test]
+    fn quad_shaders() {
+        let reflection = Reflection::new(&[shader::QUAD_VERT, shader::QUAD_FRAG]).unwrap();
+        assert_eq!(reflection.shaders[0].stage, ShaderStageFlags::VERTEX);
+        assert_eq!(reflection.shaders[0].inputs, vec![(0, Format::Rg32Float), (1, Format::Rg32Float)]);
+        assert_eq!(reflection.shaders[1].stage, ShaderStageFlags::FRAGMENT);
+        assert_eq!(reflection.bindings, vec![
+            binding(0, 0, DescriptorType::SampledImage, 1, ShaderStageFlags::FRAGMENT),
+            binding(0, 1, DescriptorType::Sampler, 1, ShaderStageFlags::FRAGMENT),
+        ]);
+        assert!(reflection.push_constant_ranges().is_empty());
+    }
+
+    #[test]
+    fn vertex_inputs_are_sorted_by_location() {
+        let reflection = Reflection::new(&[REFLECT_VERT]).unwrap();
+        assert_eq!(reflection.shaders[0].inputs, vec![
+            (0, Format::Rgb32Float),
+            (1, Format::Rg32Int),
+            (3, Format::Rgba32Float),
+        ]);
+    }
+
+    #[test]
+    fn bindings_are_merged_across_stages() {
+        let reflection = Reflection::new(&[REFLECT_VERT, REFLECT_FRAG]).unwrap();
+        assert_eq!(reflection.bindings, vec![
+            binding(0, 0, DescriptorType::UniformBuffer, 1, ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT),
+            binding(0, 1, DescriptorType::StorageBuffer, 1, ShaderStageFlags::FRAGMENT),
+            binding(1, 2, DescriptorType::CombinedImageSampler, 4, ShaderStageFlags::VERTEX),
+        ]);
+        assert_eq!(reflection.set_layout_bindings(1).len(), 1);
+        assert_eq!(reflection.set_layout_bindings(1)[0].count, 4);
+
+        let ranges = reflection.descriptor_ranges(0, 3);
+        assert_eq!(ranges.len(), 2);
+        assert_eq!((ranges[0].ty, ranges[0].count), (DescriptorType::UniformBuffer, 3));
+        assert_eq!((ranges[1].ty, ranges[1].count), (DescriptorType::StorageBuffer, 3));
+        let ranges = reflection.descriptor_ranges(1, 3);
+        assert_eq!((ranges[0].ty, ranges[0].count), (DescriptorType::CombinedImageSampler, 12));
+    }
+
+    #[test]
+    fn push_constant_ranges() {
+        // 顶点着色器: mat4 + vec4 = 80字节; 片段着色器: 偏移量80处的float, 共84字节
+        let reflection = Reflection::new(&[REFLECT_VERT, REFLECT_FRAG]).unwrap();
+        assert_eq!(reflection.push_constant_ranges(), vec![
+            (ShaderStageFlags::VERTEX, 0..20),
+            (ShaderStageFlags::FRAGMENT, 0..21),
+        ]);
+    }
+
+    #[test]
+    fn binding_type_mismatch() {
+        // 两个着色器中set 0 binding 0分别是uniform缓冲和采样图像
+        match Reflection::new(&[REFLECT_VERT, shader::QUAD_FRAG]) {
+            Err(ReflectError::BindingMismatch { set: 0, binding: 0, first, second }) => {
+                assert_eq!(first, DescriptorType::UniformBuffer);
+                assert_eq!(second, DescriptorType::SampledImage);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn layout_check() {
+        let quad = Reflection::new(&[shader::QUAD_VERT, shader::QUAD_FRAG]).unwrap();
+        let other = Reflection::new(&[REFLECT_VERT, REFLECT_FRAG]).unwrap();
+        assert!(quad.check_layout(&quad.clone()).is_ok());
+        match quad.check_layout(&other) {
+            Err(ReflectError::LayoutMismatch) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn vertex_attribute_check() {
+        let reflection = Reflection::new(&[shader::QUAD_VERT, shader::QUAD_FRAG]).unwrap();
+        let attributes = [attribute(0, Format::Rg32Float), attribute(1, Format::Rg32Float)];
+        assert!(reflection.check_vertex_attributes(&attributes).is_ok());
+
+        match reflection.check_vertex_attributes(&[attribute(0, Format::Rg32Float), attribute(1, Format::Rgb32Float)]) {
+            Err(ReflectError::AttributeMismatch { location: 1, shader: Format::Rg32Float, vertex: Some(Format::Rgb32Float) }) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        match reflection.check_vertex_attributes(&attributes[..1]) {
+            Err(ReflectError::AttributeMismatch { location: 1, vertex: None, .. }) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn invalid_spirv() {
+        let invalid = |bytes: &[u8]| match Reflection::new(&[bytes]) {
+            Err(ReflectError::InvalidSpirv(reason)) => reason,
+            other => panic!("unexpected result {:?}", other),
+        };
+        assert_eq!(invalid(&[0x03, 0x02, 0x23]), "length is not a multiple of 4");
+        assert_eq!(invalid(&[0; 20]), "bad header");
+        // 指令声明的长度超出了数据的末尾
+        assert_eq!(invalid(&spirv(&[3 << 16 | OP_NAME, 1])), "truncated instruction");
+        assert_eq!(invalid(&spirv(&[])), "missing entry point");
+    }
+
+    // 片段着色器中类型为ty的推送常数变量, 前面是words中的类型定义
+    fn push_constant_spirv(words: &[u32], ty: u32) -> Vec<u8> {
+        let mut module = vec![3 << 16 | OP_ENTRY_POINT, 4, 1];
+        module.extend_from_slice(words);
+        module.extend_from_slice(&[
+            4 << 16 | OP_TYPE_POINTER, 100, STORAGE_PUSH_CONSTANT, ty,
+            4 << 16 | OP_VARIABLE, 100, 101, STORAGE_PUSH_CONSTANT,
+        ]);
+        spirv(&module)
+    }
+
+    #[test]
+    fn recursive_types() {
+        let invalid = |bytes: &[u8]| match Reflection::new(&[bytes]) {
+            Err(ReflectError::InvalidSpirv(reason)) => reason,
+            other => panic!("unexpected result {:?}", other),
+        };
+        // 元素类型是自己的数组
+        let array = [4 << 16 | OP_TYPE_ARRAY, 1, 1, 2];
+        assert_eq!(invalid(&push_constant_spirv(&array, 1)), "types are nested too deeply");
+        // 顶点输入是元素类型为自己的数组
+        let input = spirv(&[
+            3 << 16 | OP_ENTRY_POINT, 0, 1,
+            4 << 16 | OP_TYPE_ARRAY, 1, 1, 2,
+            4 << 16 | OP_TYPE_POINTER, 100, STORAGE_INPUT, 1,
+            4 << 16 | OP_VARIABLE, 100, 101, STORAGE_INPUT,
+        ]);
+        assert_eq!(invalid(&input), "types are nested too deeply");
+    }
+
+    #[test]
+    fn type_size_overflow() {
+        let invalid = |bytes: &[u8]| match Reflection::new(&[bytes]) {
+            Err(ReflectError::InvalidSpirv(reason)) => reason,
+            other => panic!("unexpected result {:?}", other),
+        };
+        let float = [3 << 16 | OP_TYPE_FLOAT, 1, 32];
+        // 0x4000_0000个float的数组
+        let mut array = float.to_vec();
+        array.extend_from_slice(&[
+            4 << 16 | OP_CONSTANT, 0, 2, 0x4000_0000,
+            4 << 16 | OP_TYPE_ARRAY, 3, 1, 2,
+        ]);
+        assert_eq!(invalid(&push_constant_spirv(&array, 3)), "type size overflows");
+        // 偏移量接近u32::MAX的成员
+        let mut block = float.to_vec();
+        block.extend_from_slice(&[
+            3 << 16 | OP_TYPE_STRUCT, 3, 1,
+            5 << 16 | OP_MEMBER_DECORATE, 3, 0, DECORATION_OFFSET, u32::max_value() - 1,
+        ]);
+        assert_eq!(invalid(&push_constant_spirv(&block, 3)), "type size overflows");
+    }
+}
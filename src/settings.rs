@@ -0,0 +1,177 @@
+// 窗口渲染的设置, 可以通过命令行参数或者环境变量修改, 命令行参数优先
+//   --present-mode <fifo|mailbox|immediate|relaxed>  或 GFX_PRESENT_MODE
+//   --image-count <n>                                  或 GFX_IMAGE_COUNT
+//   --frames-in-flight <n>                             或 GFX_FRAMES_IN_FLIGHT
+//   --gpu-timing                                       或 GFX_GPU_TIMING=1
+//   --timestamp-period <纳秒>                          或 GFX_TIMESTAMP_PERIOD, 开启GPU计时时必须设置
+//   --no-depth                                         或 GFX_DEPTH=0
+//   --msaa <1|2|4|8>                                   或 GFX_MSAA
+//   --no-mipmaps                                       或 GFX_MIPMAPS=0
+//   --texture <路径>                                   或 GFX_TEXTURE
+// 纹理可以是PNG, JPEG, BMP, TGA, 或者块压缩的DDS和KTX2
+// 图像和帧越多吞吐量越高, 但是输入到显示的延迟也越大
+
+use hal::window::PresentMode;
+
+use std::path::PathBuf;
+
+use crate::error::AppError;
+use crate::targets::SAMPLE_COUNTS;
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    // 希望使用的呈现模式, 表面不支持时退回FIFO
+    pub present_mode: PresentMode,
+    // 交换链中的图像数量, None时使用SwapchainConfig::from_caps的默认值
+    pub image_count: Option<u32>,
+    // 可以同时计算渲染的帧数, 每一帧有自己的命令池, 命令缓冲, 信号量和栅栏
+    pub frames_in_flight: usize,
+    // 是否用时间戳查询测量每一帧的GPU时间
+    pub gpu_timing: bool,
+    // 时间戳每增加1经过的纳秒数, hal没有提供这个值, 需要根据显卡设置
+    // None表示未知, 这时不能开启GPU计时, 见gpu_timestamp_period
+    pub timestamp_period: Option<f32>,
+    // 是否使用深度附件, 设备不支持任何深度格式时也不会使用
+    pub depth: bool,
+    // 多重采样的采样数, 1表示不使用多重采样, 创建设备后还要检查设备是否支持
+    pub samples: hal::image::NumSamples,
+    // 纹理是否生成完整的mip链
+    pub mipmaps: bool,
+    // 四边形使用的纹理文件, None时使用内置的logo.png
+    pub texture: Option<PathBuf>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            present_mode: PresentMode::Fifo,
+            image_count: None,
+            frames_in_flight: 3,
+            gpu_timing: false,
+            timestamp_period: None,
+            depth: true,
+            samples: 1,
+            mipmaps: true,
+            texture: None,
+        }
+    }
+}
+
+impl Settings {
+    // 读取命令行参数和环境变量, 没有设置的项使用默认值
+    pub fn from_env() -> Result<Self, AppError> {
+        let args: Vec<String> = std::env::args().collect();
+        let mut settings = Settings::default();
+        if let Some(value) = lookup(&args, "--present-mode", "GFX_PRESENT_MODE") {
+            settings.present_mode = parse_present_mode(&value).ok_or_else(|| AppError::Setting {
+                name: "present mode",
+                value,
+            })?;
+        }
+        if let Some(value) = lookup(&args, "--image-count", "GFX_IMAGE_COUNT") {
+            settings.image_count = Some(parse_count("image count", value)?);
+        }
+        if let Some(value) = lookup(&args, "--frames-in-flight", "GFX_FRAMES_IN_FLIGHT") {
+            settings.frames_in_flight = parse_count("frames in flight", value)? as usize;
+        }
+        settings.gpu_timing = args.iter().any(|arg| arg == "--gpu-timing")
+            || std::env::var("GFX_GPU_TIMING").map_or(false, |value| value == "1");
+        settings.depth = !args.iter().any(|arg| arg == "--no-depth")
+            && std::env::var("GFX_DEPTH").map_or(true, |value| value != "0");
+        settings.mipmaps = !args.iter().any(|arg| arg == "--no-mipmaps")
+            && std::env::var("GFX_MIPMAPS").map_or(true, |value| value != "0");
+        settings.texture = lookup(&args, "--texture", "GFX_TEXTURE").map(PathBuf::from);
+        if let Some(value) = lookup(&args, "--msaa", "GFX_MSAA") {
+            settings.samples = match value.trim().parse() {
+                Ok(samples) if SAMPLE_COUNTS.contains(&samples) => samples,
+                _ => return Err(AppError::Setting { name: "msaa samples", value }),
+            };
+        }
+        if let Some(value) = lookup(&args, "--timestamp-period", "GFX_TIMESTAMP_PERIOD") {
+            settings.timestamp_period = match value.trim().parse() {
+                Ok(period) if period > 0.0 => Some(period),
+                _ => return Err(AppError::Setting { name: "timestamp period", value }),
+            };
+        }
+        settings.gpu_timestamp_period()?;
+        Ok(settings)
+    }
+
+    // 开启GPU计时时返回时间戳的周期, 没有开启时返回None
+    // 周期未知时返回错误, 而不是猜测一个值得到错误的GPU时间
+    pub fn gpu_timestamp_period(&self) -> Result<Option<f32>, AppError> {
+        match (self.gpu_timing, self.timestamp_period) {
+            (false, _) => Ok(None),
+            (true, Some(period)) => Ok(Some(period)),
+            (true, None) => Err(AppError::Setting {
+                name: "timestamp period",
+                value: "unknown, --gpu-timing requires --timestamp-period".to_string(),
+            }),
+        }
+    }
+
+    // 检查图像数量和同时渲染的帧数, 返回交换链使用的图像数量
+    // 两者都要在表面支持的范围caps.image_count之内, 同时渲染的帧数不能超过图像数量
+    pub fn validate(
+        &self,
+        caps: &hal::window::SurfaceCapabilities,
+        default_image_count: u32,
+    ) -> Result<u32, AppError> {
+        let supported = &caps.image_count;
+        let image_count = self.image_count.unwrap_or(default_image_count);
+        if image_count < supported.start || image_count > supported.end {
+            return Err(AppError::Setting {
+                name: "image count",
+                value: format!(
+                    "{}, the surface supports {} to {}",
+                    image_count, supported.start, supported.end,
+                ),
+            });
+        }
+        if self.frames_in_flight == 0 || self.frames_in_flight > image_count as usize {
+            return Err(AppError::Setting {
+                name: "frames in flight",
+                value: format!(
+                    "{}, must be between 1 and the image count {}",
+                    self.frames_in_flight, image_count,
+                ),
+            });
+        }
+        Ok(image_count)
+    }
+}
+
+// 先查找命令行参数 `flag <值>`, 再查找环境变量
+fn lookup(args: &[String], flag: &str, env: &str) -> Option<String> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|pos| args.get(pos + 1).cloned())
+        .or_else(|| std::env::var(env).ok())
+}
+
+fn parse_count(name: &'static str, value: String) -> Result<u32, AppError> {
+    match value.trim().parse() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(AppError::Setting { name, value }),
+    }
+}
+
+pub fn parse_present_mode(value: &str) -> Option<PresentMode> {
+    match value.trim().to_lowercase().as_str() {
+        "fifo" | "vsync" => Some(PresentMode::Fifo),
+        "mailbox" => Some(PresentMode::Mailbox),
+        "immediate" => Some(PresentMode::Immediate),
+        "relaxed" => Some(PresentMode::Relaxed),
+        _ => None,
+    }
+}
+
+// 表面支持wanted时使用它, 否则使用所有表面都支持的FIFO
+// 调用者可以比较返回值和wanted, 判断是否发生了回退
+pub fn choose_present_mode(wanted: PresentMode, supported: &[PresentMode]) -> PresentMode {
+    if supported.contains(&wanted) {
+        wanted
+    } else {
+        PresentMode::Fifo
+    }
+}
@@ -0,0 +1,112 @@
+// 编译时生成的spirv着色器, 见build.rs
+// debug模式下打开hot-reload功能时, 还可以监视着色器源文件, 在运行时重新编译
+
+use hal::device::Device;
+
+#[cfg(all(debug_assertions, feature = "hot-reload"))]
+use std::io::Read;
+#[cfg(all(debug_assertions, feature = "hot-reload"))]
+use std::path::{Path, PathBuf};
+#[cfg(all(debug_assertions, feature = "hot-reload"))]
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::Back;
+use crate::error::{AppError, Stage};
+
+pub(crate) const QUAD_VERT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/data/quad.vert.spv"));
+pub(crate) const QUAD_FRAG: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/data/quad.frag.spv"));
+pub(crate) const PART00_VERT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/part00.vert.spv"));
+pub(crate) const PART00_FRAG: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/part00.frag.spv"));
+
+// 用spirv创建着色器模块
+pub(crate) fn create_shader_module(
+    device: &<Back as hal::Backend>::Device,
+    spirv: &[u8],
+) -> Result<<Back as hal::Backend>::ShaderModule, AppError>
+{
+    unsafe { device.create_shader_module(spirv) }.stage("create shader module")
+}
+
+// 开发时使用: 监视着色器源文件, 文件修改后用glsl_to_spirv重新编译
+#[cfg(all(debug_assertions, feature = "hot-reload"))]
+pub(crate) struct ShaderWatcher {
+    shaders: Vec<WatchedShader>,
+    // 上一次检查文件的时间, 避免每一帧都读取文件信息
+    last_check: Instant,
+}
+
+#[cfg(all(debug_assertions, feature = "hot-reload"))]
+struct WatchedShader {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+#[cfg(all(debug_assertions, feature = "hot-reload"))]
+impl ShaderWatcher {
+    // 两次检查之间的最短间隔
+    const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+    // 着色器的类型由扩展名决定, 和build.rs相同
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        let shaders = paths
+            .into_iter()
+            .map(|path| {
+                let modified = Self::modified(&path);
+                WatchedShader { path, modified }
+            })
+            .collect();
+        Self {
+            shaders,
+            last_check: Instant::now(),
+        }
+    }
+
+    // 任意一个文件发生变化时, 按照传入的顺序重新编译所有着色器
+    // 没有变化时返回None, 编译失败时返回编译器的错误信息
+    pub fn poll(&mut self) -> Option<Result<Vec<Vec<u8>>, AppError>> {
+        if self.last_check.elapsed() < Self::CHECK_INTERVAL {
+            return None;
+        }
+        self.last_check = Instant::now();
+
+        let mut changed = false;
+        for shader in &mut self.shaders {
+            let modified = Self::modified(&shader.path);
+            if modified != shader.modified {
+                shader.modified = modified;
+                changed = true;
+            }
+        }
+        if !changed {
+            return None;
+        }
+
+        Some(
+            self.shaders
+                .iter()
+                .map(Self::compile)
+                .collect::<Result<_, _>>()
+                .map_err(AppError::ShaderCompile),
+        )
+    }
+
+    fn modified(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|meta| meta.modified()).ok()
+    }
+
+    fn compile(shader: &WatchedShader) -> Result<Vec<u8>, String> {
+        let ty = match shader.path.extension().and_then(|ext| ext.to_str()) {
+            Some("vert") => glsl_to_spirv::ShaderType::Vertex,
+            Some("frag") => glsl_to_spirv::ShaderType::Fragment,
+            _ => return Err(format!("{}: unknown shader type", shader.path.display())),
+        };
+        let glsl = std::fs::read_to_string(&shader.path)
+            .map_err(|err| format!("{}: {}", shader.path.display(), err))?;
+        let mut file = glsl_to_spirv::compile(&glsl, ty)
+            .map_err(|err| format!("{}:\n{}", shader.path.display(), err))?;
+        let mut spirv = Vec::new();
+        file.read_to_end(&mut spirv)
+            .map_err(|err| format!("{}: {}", shader.path.display(), err))?;
+        Ok(spirv)
+    }
+}
@@ -0,0 +1,146 @@
+// 帧缓冲中除了交换链图像(或离屏渲染的图像)之外的附件: 多重采样的颜色附件和深度附件
+// 它们和渲染目标的尺寸相同, 尺寸改变时需要重新创建
+
+use hal::device::Device;
+
+use crate::Back;
+use crate::depth::{create_attachment, DepthTarget};
+use crate::error::{AppError, Stage};
+use crate::memory::{Allocation, Allocator};
+use crate::pass::PassDesc;
+
+// 可以使用的采样数
+pub const SAMPLE_COUNTS: [hal::image::NumSamples; 4] = [1, 2, 4, 8];
+
+// 检查设备是否支持samples个采样的帧缓冲, 使用深度附件时深度附件也要支持
+pub fn validate_samples(
+    limits: &hal::Limits,
+    samples: hal::image::NumSamples,
+    depth: bool,
+) -> Result<hal::image::NumSamples, AppError>
+{
+    // 采样数的位掩码, 第n位表示支持2^n个采样
+    let mut supported = limits.framebuffer_color_samples_count;
+    if depth {
+        supported &= limits.framebuffer_depth_samples_count;
+    }
+    if SAMPLE_COUNTS.contains(&samples) && supported & samples != 0 {
+        return Ok(samples);
+    }
+    let supported: Vec<_> = SAMPLE_COUNTS
+        .iter()
+        .filter(|&&count| supported & count != 0)
+        .collect();
+    Err(AppError::Setting {
+        name: "msaa samples",
+        value: format!("{}, the device supports {:?}", samples, supported),
+    })
+}
+
+// 多重采样的颜色附件, render pass结束时解析到单采样的图像中
+// 内容不需要保存, 所以使用TRANSIENT_ATTACHMENT
+pub struct ColorTarget {
+    image: <Back as hal::Backend>::Image,
+    memory: Allocation,
+    pub view: <Back as hal::Backend>::ImageView,
+}
+
+impl ColorTarget {
+    pub fn new(
+        device: &<Back as hal::Backend>::Device,
+        allocator: &mut Allocator,
+        format: hal::format::Format,
+        extent: hal::image::Extent,
+        samples: hal::image::NumSamples,
+    ) -> Result<Self, AppError>
+    {
+        let (image, memory, view) = create_attachment(
+            device,
+            allocator,
+            format,
+            extent,
+            samples,
+            hal::image::Usage::COLOR_ATTACHMENT | hal::image::Usage::TRANSIENT_ATTACHMENT,
+            hal::format::Aspects::COLOR,
+        ).stage("create multisampled color target")?;
+        Ok(ColorTarget { image, memory, view })
+    }
+
+    // 销毁颜色图像, 调用前需要确保设备已经不再使用它
+    pub unsafe fn destroy(self, device: &<Back as hal::Backend>::Device, allocator: &mut Allocator) {
+        device.destroy_image_view(self.view);
+        device.destroy_image(self.image);
+        allocator.free(device, self.memory);
+    }
+}
+
+// 一个渲染目标尺寸下的所有附加附件, 按照pass_desc创建, 顺序和create_render_pass中的附件一致
+pub struct RenderTargets {
+    // 采样数大于1时才有
+    color: Option<ColorTarget>,
+    depth: Option<DepthTarget>,
+}
+
+impl RenderTargets {
+    pub fn new(
+        device: &<Back as hal::Backend>::Device,
+        allocator: &mut Allocator,
+        pass_desc: &PassDesc,
+        extent: hal::image::Extent,
+    ) -> Result<Self, AppError>
+    {
+        let PassDesc { format, depth_format, samples } = *pass_desc;
+        let color = if samples > 1 {
+            Some(ColorTarget::new(device, allocator, format, extent, samples)?)
+        } else {
+            None
+        };
+        let depth = match depth_format.map(|depth_format| {
+            DepthTarget::new(device, allocator, depth_format, extent, samples)
+        }) {
+            Some(Ok(depth)) => Some(depth),
+            Some(Err(err)) => {
+                // 深度附件创建失败时, 销毁已经创建的颜色附件
+                if let Some(color) = color {
+                    unsafe {
+                        color.destroy(device, allocator);
+                    }
+                }
+                return Err(err);
+            }
+            None => None,
+        };
+        Ok(RenderTargets { color, depth })
+    }
+
+    // 帧缓冲的附件: 颜色, 深度, 多重采样时最后是解析目标
+    // target是最终的渲染结果, 也就是交换链图像或者离屏渲染的图像
+    pub fn attachments<'a>(
+        &'a self,
+        target: &'a <Back as hal::Backend>::ImageView,
+    ) -> Vec<&'a <Back as hal::Backend>::ImageView>
+    {
+        let mut attachments = Vec::with_capacity(3);
+        match self.color {
+            Some(ref color) => attachments.push(&color.view),
+            None => attachments.push(target),
+        }
+        if let Some(ref depth) = self.depth {
+            attachments.push(&depth.view);
+        }
+        if self.color.is_some() {
+            attachments.push(target);
+        }
+        attachments
+    }
+
+    // 销毁所有附件, 调用前需要确保设备已经不再使用它们
+    pub unsafe fn destroy(self, device: &<Back as hal::Backend>::Device, allocator: &mut Allocator) {
+        if let Some(color) = self.color {
+            color.destroy(device, allocator);
+        }
+        if let Some(depth) = self.depth {
+            depth.destroy(device, allocator);
+        }
+    }
+}
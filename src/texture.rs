@@ -0,0 +1,445 @@
+// 从文件或者内存中加载纹理: 用image crate解码PNG, JPEG, BMP和TGA, 统一转换成rgba8后上传
+// DDS和KTX2中的块压缩数据在设备支持时直接上传, 否则在CPU上解码成rgba8
+// 每个纹理有自己的image view和采样器, 需要时带有完整的mip链
+
+use hal::{
+    adapter::PhysicalDevice,
+    device::Device,
+};
+
+use std::path::Path;
+
+use crate::Back;
+use crate::container::{CompressedImage, ContainerError};
+use crate::decompress;
+use crate::error::{AppError, Stage};
+use crate::memory::{Allocation, Allocator, Strategy};
+use crate::mipmap;
+use crate::queues::Queues;
+use crate::upload::{ImageData, MipLevel, TexelBlock, Upload};
+
+// rgba每个像素占4个字节
+const PIXEL_SIZE: u32 = 4;
+
+// 像素的颜色空间: 颜色贴图通常是sRGB, 法线和粗糙度等数据贴图是线性的
+// sRGB格式在采样时由硬件转换到线性空间
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
+impl ColorSpace {
+    pub fn format(self) -> hal::format::Format {
+        match self {
+            ColorSpace::Srgb => hal::format::Format::Rgba8Srgb,
+            ColorSpace::Linear => hal::format::Format::Rgba8Unorm,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureOptions {
+    pub color_space: ColorSpace,
+    // 是否生成完整的mip链, 缩小时使用三线性过滤
+    pub mipmaps: bool,
+    pub wrap_mode: hal::image::WrapMode,
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        TextureOptions {
+            color_space: ColorSpace::Srgb,
+            mipmaps: true,
+            wrap_mode: hal::image::WrapMode::Clamp,
+        }
+    }
+}
+
+pub struct Texture {
+    image: <Back as hal::Backend>::Image,
+    memory: Allocation,
+    pub view: <Back as hal::Backend>::ImageView,
+    pub sampler: <Back as hal::Backend>::Sampler,
+    width: u32,
+    height: u32,
+    levels: hal::image::Level,
+    layers: hal::image::Layer,
+}
+
+impl Texture {
+    // 读取并解码文件, 先按扩展名判断格式, 扩展名未知时再按文件内容判断
+    // .dds和.ktx2文件作为块压缩纹理加载
+    pub fn from_path<P: AsRef<Path>>(
+        adapter: &hal::Adapter<Back>,
+        device: &<Back as hal::Backend>::Device,
+        allocator: &mut Allocator,
+        queues: &mut Queues,
+        path: P,
+        options: TextureOptions,
+    ) -> Result<Self, AppError>
+    {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).stage("read texture file")?;
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+        let format = match extension.as_ref().map(|extension| extension.as_str()) {
+            Some("dds") | Some("ktx2") => {
+                let compressed = CompressedImage::parse(&bytes).stage("read texture container")?;
+                return Self::from_compressed(adapter, device, allocator, queues, &compressed, options);
+            }
+            Some("png") => image::ImageFormat::PNG,
+            Some("jpg") | Some("jpeg") => image::ImageFormat::JPEG,
+            Some("bmp") => image::ImageFormat::BMP,
+            Some("tga") => image::ImageFormat::TGA,
+            _ => guess_format(&bytes)?,
+        };
+        let img = image::load_from_memory_with_format(&bytes, format)
+            .stage("decode texture")?
+            .to_rgba();
+        Self::from_image(adapter, device, allocator, queues, &img, options)
+    }
+
+    // 按内容判断格式并解码, TGA没有文件头标识, 只能通过from_path按扩展名加载
+    pub fn from_memory(
+        adapter: &hal::Adapter<Back>,
+        device: &<Back as hal::Backend>::Device,
+        allocator: &mut Allocator,
+        queues: &mut Queues,
+        bytes: &[u8],
+        options: TextureOptions,
+    ) -> Result<Self, AppError>
+    {
+        if CompressedImage::is_container(bytes) {
+            let compressed = CompressedImage::parse(bytes).stage("read texture container")?;
+            return Self::from_compressed(adapter, device, allocator, queues, &compressed, options);
+        }
+        let format = guess_format(bytes)?;
+        let img = image::load_from_memory_with_format(bytes, format)
+            .stage("decode texture")?
+            .to_rgba();
+        Self::from_image(adapter, device, allocator, queues, &img, options)
+    }
+
+    // 上传已经解码的图片, 上传和mip链生成完成后才会返回
+    pub fn from_image(
+        adapter: &hal::Adapter<Back>,
+        device: &<Back as hal::Backend>::Device,
+        allocator: &mut Allocator,
+        queues: &mut Queues,
+        img: &image::RgbaImage,
+        options: TextureOptions,
+    ) -> Result<Self, AppError>
+    {
+        let format = options.color_space.format();
+        let (width, height) = img.dimensions();
+        let levels = if options.mipmaps { mipmap::mip_levels(width, height) } else { 1 };
+        // 用blit生成mip链时, 每一层还要作为下一层的源
+        let blit = levels > 1 && mipmap::supports_blit(&adapter.physical_device, format);
+        let mut usage = hal::image::Usage::TRANSFER_DST | hal::image::Usage::SAMPLED;
+        if blit {
+            usage |= hal::image::Usage::TRANSFER_SRC;
+        }
+
+        let (image, memory) = create_image(
+            device,
+            allocator,
+            hal::image::Kind::D2(width, height, 1, 1),
+            levels,
+            format,
+            usage,
+        )?;
+
+        // 不支持blit时在CPU上生成其余层级, 和第0层一起上传
+        let cpu_levels = if blit {
+            Vec::new()
+        } else {
+            mipmap::generate_cpu(img, levels)
+        };
+        let mip_levels: Vec<_> = Some(img)
+            .into_iter()
+            .chain(&cpu_levels)
+            .map(|level| MipLevel {
+                width: level.width(),
+                height: level.height(),
+                pixels: level,
+            })
+            .collect();
+        let data = ImageData {
+            block: TexelBlock::pixel(PIXEL_SIZE),
+            layers: 1,
+            levels: &mip_levels,
+        };
+        let result = upload_levels(adapter, device, allocator, queues, &image, &data)
+            .and_then(|()| if blit {
+                mipmap::generate_blit(device, queues, &image, width, height, levels)
+            } else {
+                Ok(())
+            })
+            .and_then(|()| create_view_and_sampler(device, &image, format, levels, 1, options.wrap_mode));
+        let (view, sampler) = match result {
+            Ok(view_and_sampler) => view_and_sampler,
+            Err(err) => {
+                unsafe {
+                    device.destroy_image(image);
+                    allocator.free(device, memory);
+                }
+                return Err(err);
+            }
+        };
+        Ok(Texture {
+            image,
+            memory,
+            view,
+            sampler,
+            width,
+            height,
+            levels,
+            layers: 1,
+        })
+    }
+
+    // 上传DDS或者KTX2中的块压缩数据, 使用文件中的mip链, 不再生成
+    // 颜色空间由文件中的格式决定, options.color_space和options.mipmaps不起作用
+    // 设备不能以optimal tiling采样这个格式时, 在CPU上解码成rgba8再上传
+    pub fn from_compressed(
+        adapter: &hal::Adapter<Back>,
+        device: &<Back as hal::Backend>::Device,
+        allocator: &mut Allocator,
+        queues: &mut Queues,
+        compressed: &CompressedImage,
+        options: TextureOptions,
+    ) -> Result<Self, AppError>
+    {
+        let (width, height) = (compressed.width, compressed.height);
+        let levels = compressed.levels.len() as hal::image::Level;
+        let layers = compressed.layers;
+        let block_format = compressed.format.format(compressed.srgb);
+        let (format, block, decoded) = if supports_sampling(&adapter.physical_device, block_format) {
+            (block_format, TexelBlock { width: 4, height: 4, size: compressed.format.block_size() }, None)
+        } else {
+            let decoded = decompress_levels(compressed).ok_or_else(|| {
+                AppError::Container(ContainerError::UnsupportedFormat(format!(
+                    "{:?} (not supported by the device)",
+                    block_format,
+                )))
+            }).stage("decompress texture")?;
+            let color_space = if compressed.srgb { ColorSpace::Srgb } else { ColorSpace::Linear };
+            (color_space.format(), TexelBlock::pixel(PIXEL_SIZE), Some(decoded))
+        };
+
+        let (image, memory) = create_image(
+            device,
+            allocator,
+            hal::image::Kind::D2(width, height, layers, 1),
+            levels,
+            format,
+            hal::image::Usage::TRANSFER_DST | hal::image::Usage::SAMPLED,
+        )?;
+
+        let data = decoded.as_ref().unwrap_or(&compressed.levels);
+        let mip_levels: Vec<_> = data
+            .iter()
+            .enumerate()
+            .map(|(level, pixels)| {
+                let (width, height) = compressed.level_extent(level);
+                MipLevel { width, height, pixels }
+            })
+            .collect();
+        let data = ImageData {
+            block,
+            layers,
+            levels: &mip_levels,
+        };
+        let result = upload_levels(adapter, device, allocator, queues, &image, &data)
+            .and_then(|()| create_view_and_sampler(device, &image, format, levels, layers, options.wrap_mode));
+        let (view, sampler) = match result {
+            Ok(view_and_sampler) => view_and_sampler,
+            Err(err) => {
+                unsafe {
+                    device.destroy_image(image);
+                    allocator.free(device, memory);
+                }
+                return Err(err);
+            }
+        };
+        Ok(Texture {
+            image,
+            memory,
+            view,
+            sampler,
+            width,
+            height,
+            levels,
+            layers,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    // mip链的层数, 没有生成mip链时为1
+    pub fn levels(&self) -> hal::image::Level {
+        self.levels
+    }
+
+    // 数组层数, 立方体贴图的每个面算作一层
+    pub fn layers(&self) -> hal::image::Layer {
+        self.layers
+    }
+
+    // 销毁纹理, 调用前需要确保设备已经不再使用它
+    pub unsafe fn destroy(self, device: &<Back as hal::Backend>::Device, allocator: &mut Allocator) {
+        device.destroy_sampler(self.sampler);
+        device.destroy_image_view(self.view);
+        device.destroy_image(self.image);
+        allocator.free(device, self.memory);
+    }
+}
+
+// 按文件头判断格式, 只接受PNG, JPEG和BMP
+fn guess_format(bytes: &[u8]) -> Result<image::ImageFormat, AppError> {
+    match image::guess_format(bytes).stage("detect texture format")? {
+        format @ image::ImageFormat::PNG
+        | format @ image::ImageFormat::JPEG
+        | format @ image::ImageFormat::BMP => Ok(format),
+        format => Err(AppError::Decode(image::ImageError::UnsupportedError(
+            format!("{:?} textures are not supported", format),
+        ))).stage("detect texture format"),
+    }
+}
+
+// 创建optimal tiling的二维图片并绑定内存, 绑定失败时销毁图片
+fn create_image(
+    device: &<Back as hal::Backend>::Device,
+    allocator: &mut Allocator,
+    kind: hal::image::Kind,
+    levels: hal::image::Level,
+    format: hal::format::Format,
+    usage: hal::image::Usage,
+) -> Result<(<Back as hal::Backend>::Image, Allocation), AppError>
+{
+    let mut image = unsafe {
+        device.create_image(
+            kind,
+            levels,
+            format,
+            hal::image::Tiling::Optimal,
+            usage,
+            hal::image::ViewCapabilities::empty(),
+        )
+    }.stage("create texture")?;
+    // 纹理只由GPU读取, 最好放在DEVICE_LOCAL的内存中
+    let memory = unsafe {
+        allocator.bind_image(
+            device,
+            &mut image,
+            hal::memory::Properties::empty(),
+            hal::memory::Properties::DEVICE_LOCAL,
+            Strategy::General,
+        )
+    }.stage("allocate texture memory");
+    match memory {
+        Ok(memory) => Ok((image, memory)),
+        Err(err) => {
+            unsafe { device.destroy_image(image) };
+            Err(err)
+        }
+    }
+}
+
+// 把data上传到image, 上传完成后才会返回; 记录命令时出错会取消上传, image由调用者销毁
+fn upload_levels(
+    adapter: &hal::Adapter<Back>,
+    device: &<Back as hal::Backend>::Device,
+    allocator: &mut Allocator,
+    queues: &mut Queues,
+    image: &<Back as hal::Backend>::Image,
+    data: &ImageData,
+) -> Result<(), AppError>
+{
+    let mut upload = Upload::begin(device, queues)?;
+    if let Err(err) = upload.upload_image(adapter, device, allocator, image, data) {
+        unsafe { upload.cancel(device, allocator) };
+        return Err(err);
+    }
+    upload.submit(device, allocator, queues)?.wait(device, allocator)
+}
+
+// 多层的纹理使用数组视图, 采样器使用三线性过滤: 层级内和相邻层级之间都线性插值
+fn create_view_and_sampler(
+    device: &<Back as hal::Backend>::Device,
+    image: &<Back as hal::Backend>::Image,
+    format: hal::format::Format,
+    levels: hal::image::Level,
+    layers: hal::image::Layer,
+    wrap_mode: hal::image::WrapMode,
+) -> Result<(<Back as hal::Backend>::ImageView, <Back as hal::Backend>::Sampler), AppError>
+{
+    let kind = if layers > 1 { hal::image::ViewKind::D2Array } else { hal::image::ViewKind::D2 };
+    let view = unsafe {
+        device.create_image_view(
+            image,
+            kind,
+            format,
+            hal::format::Swizzle::NO,
+            hal::image::SubresourceRange {
+                aspects: hal::format::Aspects::COLOR,
+                levels: 0..levels,
+                layers: 0..layers,
+            },
+        )
+    }.stage("create texture view")?;
+    let mut sampler_info = hal::image::SamplerInfo::new(hal::image::Filter::Linear, wrap_mode);
+    sampler_info.mip_filter = hal::image::Filter::Linear;
+    sampler_info.lod_range = hal::image::Lod::from(0.0)..hal::image::Lod::from(levels as f32);
+    let sampler = match unsafe { device.create_sampler(sampler_info) } {
+        Ok(sampler) => sampler,
+        Err(err) => {
+            unsafe { device.destroy_image_view(view) };
+            return Err(err).stage("create sampler");
+        }
+    };
+    Ok((view, sampler))
+}
+
+// format在optimal tiling下能否被采样并线性过滤
+fn supports_sampling(
+    physical_device: &<Back as hal::Backend>::PhysicalDevice,
+    format: hal::format::Format,
+) -> bool
+{
+    physical_device
+        .format_properties(Some(format))
+        .optimal_tiling
+        .contains(hal::format::ImageFeature::SAMPLED | hal::format::ImageFeature::SAMPLED_LINEAR)
+}
+
+// 在CPU上把每个层级的每个数组层解码成rgba8, 格式不能在CPU上解码时返回None
+fn decompress_levels(compressed: &CompressedImage) -> Option<Vec<Vec<u8>>> {
+    if !decompress::supports(compressed.format) {
+        return None;
+    }
+    compressed
+        .levels
+        .iter()
+        .enumerate()
+        .map(|(level, data)| {
+            let (width, height) = compressed.level_extent(level);
+            let layer_size = compressed.layer_size(level);
+            let layer_pixels = width as usize * height as usize * PIXEL_SIZE as usize;
+            let mut pixels = Vec::with_capacity(data.len() / layer_size * layer_pixels);
+            for layer in data.chunks(layer_size) {
+                pixels.extend(decompress::decompress(compressed.format, width, height, layer)?);
+            }
+            Some(pixels)
+        })
+        .collect()
+}
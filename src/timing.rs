@@ -0,0 +1,174 @@
+// 帧时间统计
+// FrameTimer记录CPU上相邻两帧之间的时间, 保留最近的若干帧, 计算平均值, 最小值和最大值
+// GpuTimer在每一帧的render pass前后写入时间戳, 等到这一帧的栅栏发出信号后再读回
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use hal::device::Device;
+
+use crate::Back;
+use crate::error::{AppError, Stage};
+
+// 最近若干帧的帧时间
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameStats {
+    pub average: Duration,
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl FrameStats {
+    pub fn fps(&self) -> f64 {
+        1.0 / duration_secs(self.average)
+    }
+}
+
+pub fn duration_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 * 1e-9
+}
+
+pub fn duration_millis(duration: Duration) -> f64 {
+    duration_secs(duration) * 1e3
+}
+
+pub struct FrameTimer {
+    samples: VecDeque<Duration>,
+    capacity: usize,
+    last: Option<Instant>,
+}
+
+impl FrameTimer {
+    // capacity是参与统计的帧数
+    pub fn new(capacity: usize) -> Self {
+        assert_ne!(capacity, 0);
+        FrameTimer {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+            last: None,
+        }
+    }
+
+    // 每一帧调用一次, 第一次调用只记录时间
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        if let Some(last) = self.last {
+            if self.samples.len() == self.capacity {
+                self.samples.pop_front();
+            }
+            self.samples.push_back(now - last);
+        }
+        self.last = Some(now);
+    }
+
+    // 暂停之后(例如窗口最小化)调用, 避免把暂停的时间算作一帧
+    pub fn pause(&mut self) {
+        self.last = None;
+    }
+
+    pub fn stats(&self) -> Option<FrameStats> {
+        let min = *self.samples.iter().min()?;
+        let max = *self.samples.iter().max()?;
+        let total = self.samples.iter().fold(Duration::new(0, 0), |sum, sample| sum + *sample);
+        Some(FrameStats {
+            average: total / self.samples.len() as u32,
+            min,
+            max,
+        })
+    }
+}
+
+// 每一帧使用查询池中的两个时间戳: 2 * frame是开始, 2 * frame + 1是结束
+pub(crate) struct GpuTimer {
+    pool: <Back as hal::Backend>::QueryPool,
+    // 时间戳每增加1经过的纳秒数
+    period: f32,
+    // 这一帧是否写入过时间戳, 没有写入过的查询不能读取
+    written: Vec<bool>,
+    last: Option<Duration>,
+}
+
+impl GpuTimer {
+    pub fn new(
+        device: &<Back as hal::Backend>::Device,
+        frames: usize,
+        period: f32,
+    ) -> Result<Self, AppError>
+    {
+        let pool = unsafe {
+            device.create_query_pool(hal::query::Type::Timestamp, 2 * frames as hal::query::Id)
+        }.stage("create timestamp query pool")?;
+        Ok(GpuTimer {
+            pool,
+            period,
+            written: vec![false; frames],
+            last: None,
+        })
+    }
+
+    // 读取frame上一次写入的时间戳, 调用前需要确保这一帧的命令已经执行完成
+    pub unsafe fn read(
+        &mut self,
+        device: &<Back as hal::Backend>::Device,
+        frame: usize,
+    ) -> Result<(), AppError>
+    {
+        if !self.written[frame] {
+            return Ok(());
+        }
+        let first = 2 * frame as hal::query::Id;
+        let mut data = [0u8; 16];
+        device
+            .get_query_pool_results(
+                &self.pool,
+                first..first + 2,
+                &mut data,
+                8,
+                hal::query::ResultFlags::BITS_64 | hal::query::ResultFlags::WAIT,
+            )
+            .stage("read timestamps")?;
+        let mut begin = [0u8; 8];
+        let mut end = [0u8; 8];
+        begin.copy_from_slice(&data[0..8]);
+        end.copy_from_slice(&data[8..16]);
+        let ticks = u64::from_ne_bytes(end).wrapping_sub(u64::from_ne_bytes(begin));
+        self.last = Some(Duration::from_nanos((ticks as f64 * self.period as f64) as u64));
+        Ok(())
+    }
+
+    // 在render pass开始之前调用, 不能在render pass之内
+    pub unsafe fn begin<S: hal::command::Shot>(
+        &mut self,
+        cmd_buffer: &mut hal::command::CommandBuffer<Back, hal::Graphics, S>,
+        frame: usize,
+    ) {
+        let first = 2 * frame as hal::query::Id;
+        cmd_buffer.reset_query_pool(&self.pool, first..first + 2);
+        cmd_buffer.write_timestamp(
+            hal::pso::PipelineStage::TOP_OF_PIPE,
+            hal::query::Query { pool: &self.pool, id: first },
+        );
+    }
+
+    // 在render pass结束之后调用, 不能在render pass之内
+    pub unsafe fn end<S: hal::command::Shot>(
+        &mut self,
+        cmd_buffer: &mut hal::command::CommandBuffer<Back, hal::Graphics, S>,
+        frame: usize,
+    ) {
+        cmd_buffer.write_timestamp(
+            hal::pso::PipelineStage::BOTTOM_OF_PIPE,
+            hal::query::Query { pool: &self.pool, id: 2 * frame as hal::query::Id + 1 },
+        );
+        self.written[frame] = true;
+    }
+
+    // 最近一次读回的GPU时间
+    pub fn last(&self) -> Option<Duration> {
+        self.last
+    }
+
+    pub unsafe fn destroy(self, device: &<Back as hal::Backend>::Device) {
+        device.destroy_query_pool(self.pool);
+    }
+}
@@ -0,0 +1,149 @@
+// 绘制part00中的三角形所需的资源
+// 三角形的顶点直接写在着色器中, 不需要顶点缓冲和描述符
+
+const ENTRY_NAME: &str = "main";
+
+use hal::device::Device;
+
+use crate::Back;
+use crate::error::{AppError, Stage};
+use crate::pass::{self, PassDesc};
+use crate::shader;
+
+pub struct Triangle {
+    pub render_pass: <Back as hal::Backend>::RenderPass,
+    pipeline: <Back as hal::Backend>::GraphicsPipeline,
+    pipeline_layout: <Back as hal::Backend>::PipelineLayout,
+}
+
+impl Triangle {
+    pub fn new(
+        device: &<Back as hal::Backend>::Device,
+        format: hal::format::Format,
+        samples: hal::image::NumSamples,
+        final_layout: hal::image::Layout,
+    ) -> Result<Self, AppError>
+    {
+        // 三角形不需要深度测试
+        let pass_desc = PassDesc { format, depth_format: None, samples };
+        let render_pass = pass::create_render_pass(device, &pass_desc, final_layout)?;
+        // 没有描述符集合, 也没有推送常数
+        let pipeline_layout = match unsafe {
+            device.create_pipeline_layout(
+                std::iter::empty::<&<Back as hal::Backend>::DescriptorSetLayout>(),
+                &[],
+            )
+        }.stage("create pipeline layout") {
+            Ok(pipeline_layout) => pipeline_layout,
+            Err(err) => {
+                unsafe {
+                    device.destroy_render_pass(render_pass);
+                }
+                return Err(err);
+            }
+        };
+        match Self::create_pipeline(device, &render_pass, &pipeline_layout, samples) {
+            Ok(pipeline) => Ok(Self {
+                render_pass,
+                pipeline,
+                pipeline_layout,
+            }),
+            Err(err) => {
+                unsafe {
+                    device.destroy_pipeline_layout(pipeline_layout);
+                    device.destroy_render_pass(render_pass);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    // 创建着色器模块和渲染管线
+    fn create_pipeline(
+        device: &<Back as hal::Backend>::Device,
+        render_pass: &<Back as hal::Backend>::RenderPass,
+        pipeline_layout: &<Back as hal::Backend>::PipelineLayout,
+        samples: hal::image::NumSamples,
+    ) -> Result<<Back as hal::Backend>::GraphicsPipeline, AppError>
+    {
+        // 着色器在编译时已经转换成了spirv
+        let vs_module = shader::create_shader_module(device, shader::PART00_VERT)?;
+        let fs_module = match shader::create_shader_module(device, shader::PART00_FRAG) {
+            Ok(module) => module,
+            Err(err) => {
+                unsafe {
+                    device.destroy_shader_module(vs_module);
+                }
+                return Err(err);
+            }
+        };
+        let pipeline = {
+            let shader_entries = hal::pso::GraphicsShaderSet {
+                vertex: hal::pso::EntryPoint {
+                    entry: ENTRY_NAME,
+                    module: &vs_module,
+                    specialization: hal::pso::Specialization::default(),
+                },
+                hull: None,
+                domain: None,
+                geometry: None,
+                fragment: Some(hal::pso::EntryPoint {
+                    entry: ENTRY_NAME,
+                    module: &fs_module,
+                    specialization: hal::pso::Specialization::default(),
+                }),
+            };
+            let subpass = hal::pass::Subpass {
+                index: 0,
+                main_pass: render_pass,
+            };
+            let mut pipeline_desc = hal::pso::GraphicsPipelineDesc::new(
+                shader_entries,
+                hal::Primitive::TriangleList,
+                hal::pso::Rasterizer::FILL,
+                pipeline_layout,
+                subpass,
+            );
+            pipeline_desc.blender.targets.push(hal::pso::ColorBlendDesc(
+                hal::pso::ColorMask::ALL,
+                hal::pso::BlendState::ALPHA,
+            ));
+            pipeline_desc.multisampling = pass::multisampling(samples);
+            unsafe {
+                device.create_graphics_pipeline(&pipeline_desc, None)
+            }
+        };
+        unsafe {
+            device.destroy_shader_module(vs_module);
+            device.destroy_shader_module(fs_module);
+        }
+        pipeline.stage("create triangle pipeline")
+    }
+
+    // 把绘制三角形的命令记录到命令缓冲区中, 命令缓冲区的begin和finish由调用者负责
+    pub unsafe fn record<S: hal::command::Shot>(
+        &self,
+        cmd_buffer: &mut hal::command::CommandBuffer<Back, hal::Graphics, S>,
+        framebuffer: &<Back as hal::Backend>::Framebuffer,
+        viewport: &hal::pso::Viewport,
+    ) {
+        cmd_buffer.set_viewports(0, &[viewport.clone()]);
+        cmd_buffer.set_scissors(0, &[viewport.rect]);
+        cmd_buffer.bind_graphics_pipeline(&self.pipeline);
+
+        let mut encoder = cmd_buffer.begin_render_pass_inline(
+            &self.render_pass,
+            framebuffer,
+            viewport.rect,
+            &pass::clear_values(false),
+        );
+        encoder.draw(0..3, 0..1);
+    }
+
+    // 销毁所有资源, 调用前需要确保设备已经不再使用它们
+    pub unsafe fn destroy(self, device: &<Back as hal::Backend>::Device) {
+        device.destroy_render_pass(self.render_pass);
+        device.destroy_graphics_pipeline(self.pipeline);
+        device.destroy_pipeline_layout(self.pipeline_layout);
+    }
+}
@@ -0,0 +1,492 @@
+// 通过暂存缓冲上传数据: 先写入CPU可见的TRANSFER_SRC缓冲,
+// 再用复制命令复制到DEVICE_LOCAL的缓冲和图片中
+// 一个Upload中可以记录多次上传, 提交后等待栅栏, 然后回收所有暂存缓冲
+// 有专用的传输队列族时, 复制命令提交到传输队列, 之后资源的所有权需要转移给图形队列族:
+// 传输队列上释放所有权, 图形队列等待信号量后再获取所有权, 两个屏障的布局转换必须相同
+
+use hal::{
+    adapter::PhysicalDevice,
+    device::Device,
+};
+
+use std::ops::Range;
+
+use crate::Back;
+use crate::error::{AppError, Stage};
+use crate::memory::{Allocation, Allocator, Strategy};
+use crate::queues::Queues;
+
+// 图片一个mip层级的数据, 每一行紧密排列, 有多个数组层时按层依次排列
+// 压缩格式的一行是一行块, width和height仍然是这一层的像素尺寸
+pub struct MipLevel<'a> {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: &'a [u8],
+}
+
+// 数据的最小单位: 未压缩格式是1x1的像素, 块压缩格式是4x4的块
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TexelBlock {
+    pub width: u32,
+    pub height: u32,
+    // 每个像素或者每个块的字节数
+    pub size: u32,
+}
+
+impl TexelBlock {
+    pub fn pixel(size: u32) -> Self {
+        TexelBlock { width: 1, height: 1, size }
+    }
+}
+
+// 要上传到图片中的所有层级
+pub struct ImageData<'a> {
+    pub block: TexelBlock,
+    pub layers: hal::image::Layer,
+    pub levels: &'a [MipLevel<'a>],
+}
+
+// 正在记录的上传命令
+pub struct Upload {
+    cmd_pool: hal::CommandPool<Back, hal::Transfer>,
+    cmd_buffer: hal::command::CommandBuffer<Back, hal::Transfer, hal::command::OneShot>,
+    // 传输队列族和图形队列族不同时, 在图形队列上获取所有权的命令
+    acquire: Option<Acquire>,
+    staging: Vec<(<Back as hal::Backend>::Buffer, Allocation)>,
+}
+
+struct Acquire {
+    cmd_pool: hal::CommandPool<Back, hal::Graphics>,
+    cmd_buffer: hal::command::CommandBuffer<Back, hal::Graphics, hal::command::OneShot>,
+    // 从传输队列族转移到图形队列族
+    families: Range<hal::queue::QueueFamilyId>,
+}
+
+// 已经提交的上传命令, 栅栏发出信号后才能回收暂存缓冲和命令池
+pub struct PendingUpload {
+    fence: <Back as hal::Backend>::Fence,
+    semaphore: Option<<Back as hal::Backend>::Semaphore>,
+    cmd_pool: hal::CommandPool<Back, hal::Transfer>,
+    acquire_pool: Option<hal::CommandPool<Back, hal::Graphics>>,
+    staging: Vec<(<Back as hal::Backend>::Buffer, Allocation)>,
+}
+
+impl Upload {
+    // 在queues.upload_family()上创建只使用一次的命令池
+    pub fn begin(
+        device: &<Back as hal::Backend>::Device,
+        queues: &Queues,
+    ) -> Result<Self, AppError>
+    {
+        let upload_family = queues.upload_family();
+        let graphics_family = queues.graphics.family();
+        unsafe {
+            // 图形队列族也支持传输能力, 所以命令池的类型总是Transfer
+            let mut cmd_pool = hal::CommandPool::<Back, hal::Transfer>::new(
+                device
+                    .create_command_pool(upload_family, hal::pool::CommandPoolCreateFlags::TRANSIENT)
+                    .stage("create upload command pool")?,
+            );
+            let mut cmd_buffer = cmd_pool.acquire_command_buffer::<hal::command::OneShot>();
+            cmd_buffer.begin();
+
+            let acquire = if upload_family != graphics_family {
+                let mut cmd_pool = match device
+                    .create_command_pool_typed(
+                        &queues.graphics,
+                        hal::pool::CommandPoolCreateFlags::TRANSIENT,
+                    )
+                    .stage("create ownership command pool")
+                {
+                    Ok(cmd_pool) => cmd_pool,
+                    Err(err) => {
+                        device.destroy_command_pool(cmd_pool.into_raw());
+                        return Err(err);
+                    }
+                };
+                let mut cmd_buffer = cmd_pool.acquire_command_buffer::<hal::command::OneShot>();
+                cmd_buffer.begin();
+                Some(Acquire {
+                    cmd_pool,
+                    cmd_buffer,
+                    families: upload_family..graphics_family,
+                })
+            } else {
+                None
+            };
+
+            Ok(Self {
+                cmd_pool,
+                cmd_buffer,
+                acquire,
+                staging: Vec::new(),
+            })
+        }
+    }
+
+    // 创建DEVICE_LOCAL的缓冲区, 并记录从暂存缓冲复制data的命令
+    pub fn create_buffer<T: Copy>(
+        &mut self,
+        device: &<Back as hal::Backend>::Device,
+        allocator: &mut Allocator,
+        usage: hal::buffer::Usage,
+        data: &[T],
+    ) -> Result<(<Back as hal::Backend>::Buffer, Allocation), AppError>
+    {
+        let size = (data.len() * std::mem::size_of::<T>()) as u64;
+        assert_ne!(size, 0);
+        let staging = self.stage::<T, _>(device, allocator, size, |mapping| {
+            mapping[0..data.len()].copy_from_slice(data);
+        })?;
+
+        let mut buffer = unsafe {
+            device.create_buffer(size, usage | hal::buffer::Usage::TRANSFER_DST)
+        }.stage("create buffer")?;
+        // 顶点和索引只由GPU读取, 最好放在DEVICE_LOCAL的内存中
+        let memory = match unsafe {
+            allocator.bind_buffer(
+                device,
+                &mut buffer,
+                hal::memory::Properties::empty(),
+                hal::memory::Properties::DEVICE_LOCAL,
+                Strategy::General,
+            )
+        }.stage("allocate buffer memory") {
+            Ok(memory) => memory,
+            Err(err) => {
+                unsafe {
+                    device.destroy_buffer(buffer);
+                }
+                return Err(err);
+            }
+        };
+
+        unsafe {
+            self.cmd_buffer.copy_buffer(
+                &self.staging[staging].0,
+                &buffer,
+                &[hal::command::BufferCopy {
+                    src: 0,
+                    dst: 0,
+                    size,
+                }],
+            );
+            // 只有一个队列族时, 所有缓冲共用submit中的屏障
+            if let Some(ref mut acquire) = self.acquire {
+                let release: hal::memory::Barrier<Back> = hal::memory::Barrier::Buffer {
+                    states: hal::buffer::Access::TRANSFER_WRITE..hal::buffer::Access::empty(),
+                    target: &buffer,
+                    families: Some(acquire.families.clone()),
+                    range: None..None,
+                };
+                self.cmd_buffer.pipeline_barrier(
+                    hal::pso::PipelineStage::TRANSFER..hal::pso::PipelineStage::BOTTOM_OF_PIPE,
+                    hal::memory::Dependencies::empty(),
+                    &[release],
+                );
+                let acquire_barrier: hal::memory::Barrier<Back> = hal::memory::Barrier::Buffer {
+                    states: hal::buffer::Access::empty()
+                        ..hal::buffer::Access::VERTEX_BUFFER_READ | hal::buffer::Access::INDEX_BUFFER_READ,
+                    target: &buffer,
+                    families: Some(acquire.families.clone()),
+                    range: None..None,
+                };
+                acquire.cmd_buffer.pipeline_barrier(
+                    hal::pso::PipelineStage::TOP_OF_PIPE..hal::pso::PipelineStage::VERTEX_INPUT,
+                    hal::memory::Dependencies::empty(),
+                    &[acquire_barrier],
+                );
+            }
+        }
+        Ok((buffer, memory))
+    }
+
+    // 记录把data复制到图片前data.levels.len()个mip层级的命令
+    // 复制完成后这些层级的布局为ShaderReadOnlyOptimal, 其余层级保持Undefined
+    pub fn upload_image(
+        &mut self,
+        adapter: &hal::Adapter<Back>,
+        device: &<Back as hal::Backend>::Device,
+        allocator: &mut Allocator,
+        image: &<Back as hal::Backend>::Image,
+        data: &ImageData,
+    ) -> Result<(), AppError>
+    {
+        assert!(!data.levels.is_empty());
+        let block = data.block;
+        let limits = adapter.physical_device.limits();
+        // 缓冲区中每一行的数据需要按照min_buffer_copy_pitch_alignment对齐
+        // 对齐值和块的字节数都是2的幂, 所以对齐后的行距仍然是整数个块
+        let row_alignment_mask = limits.min_buffer_copy_pitch_alignment as u32 - 1;
+        let mut copies = Vec::with_capacity(data.levels.len());
+        for (level, level_data) in data.levels.iter().enumerate() {
+            let blocks_wide = (level_data.width + block.width - 1) / block.width;
+            let blocks_high = (level_data.height + block.height - 1) / block.height;
+            let row_size = (blocks_wide * block.size) as usize;
+            let row_pitch = (blocks_wide * block.size + row_alignment_mask) & !row_alignment_mask;
+            debug_assert_eq!(row_pitch % block.size, 0);
+            // 所有数组层的行依次排列
+            let rows = (blocks_high * data.layers as u32) as usize;
+            let upload_size = rows as u64 * row_pitch as u64;
+            let staging = self.stage::<u8, _>(device, allocator, upload_size, |mapping| {
+                for y in 0..rows {
+                    let row = &level_data.pixels[y * row_size..(y + 1) * row_size];
+                    let dest_base = y * row_pitch as usize;
+                    mapping[dest_base..dest_base + row_size].copy_from_slice(row);
+                }
+            })?;
+            // buffer_width和buffer_height以像素为单位, 需要是块尺寸的整数倍
+            copies.push((staging, hal::command::BufferImageCopy {
+                buffer_offset: 0,
+                buffer_width: row_pitch / block.size * block.width,
+                buffer_height: blocks_high * block.height,
+                image_layers: hal::image::SubresourceLayers {
+                    aspects: hal::format::Aspects::COLOR,
+                    level: level as hal::image::Level,
+                    layers: 0..data.layers,
+                },
+                image_offset: hal::image::Offset { x: 0, y: 0, z: 0 },
+                image_extent: hal::image::Extent {
+                    width: level_data.width,
+                    height: level_data.height,
+                    depth: 1,
+                },
+            }));
+        }
+
+        let range = hal::image::SubresourceRange {
+            aspects: hal::format::Aspects::COLOR,
+            levels: 0..data.levels.len() as hal::image::Level,
+            layers: 0..data.layers,
+        };
+        unsafe {
+            let image_barrier: hal::memory::Barrier<Back> = hal::memory::Barrier::Image {
+                states: (hal::image::Access::empty(), hal::image::Layout::Undefined)
+                    ..(hal::image::Access::TRANSFER_WRITE, hal::image::Layout::TransferDstOptimal),
+                target: image,
+                families: None,
+                range: range.clone(),
+            };
+            self.cmd_buffer.pipeline_barrier(
+                hal::pso::PipelineStage::TOP_OF_PIPE..hal::pso::PipelineStage::TRANSFER,
+                hal::memory::Dependencies::empty(),
+                &[image_barrier],
+            );
+            for (staging, copy) in copies {
+                self.cmd_buffer.copy_buffer_to_image(
+                    &self.staging[staging].0,
+                    image,
+                    hal::image::Layout::TransferDstOptimal,
+                    &[copy],
+                );
+            }
+            match self.acquire {
+                None => {
+                    let image_barrier: hal::memory::Barrier<Back> = hal::memory::Barrier::Image {
+                        states: (hal::image::Access::TRANSFER_WRITE, hal::image::Layout::TransferDstOptimal)
+                            ..(hal::image::Access::SHADER_READ, hal::image::Layout::ShaderReadOnlyOptimal),
+                        target: image,
+                        families: None,
+                        range,
+                    };
+                    self.cmd_buffer.pipeline_barrier(
+                        hal::pso::PipelineStage::TRANSFER..hal::pso::PipelineStage::FRAGMENT_SHADER,
+                        hal::memory::Dependencies::empty(),
+                        &[image_barrier],
+                    );
+                }
+                // 释放和获取所有权的屏障中, 布局都从TransferDstOptimal转换为ShaderReadOnlyOptimal
+                Some(ref mut acquire) => {
+                    let release: hal::memory::Barrier<Back> = hal::memory::Barrier::Image {
+                        states: (hal::image::Access::TRANSFER_WRITE, hal::image::Layout::TransferDstOptimal)
+                            ..(hal::image::Access::empty(), hal::image::Layout::ShaderReadOnlyOptimal),
+                        target: image,
+                        families: Some(acquire.families.clone()),
+                        range: range.clone(),
+                    };
+                    self.cmd_buffer.pipeline_barrier(
+                        hal::pso::PipelineStage::TRANSFER..hal::pso::PipelineStage::BOTTOM_OF_PIPE,
+                        hal::memory::Dependencies::empty(),
+                        &[release],
+                    );
+                    let acquire_barrier: hal::memory::Barrier<Back> = hal::memory::Barrier::Image {
+                        states: (hal::image::Access::empty(), hal::image::Layout::TransferDstOptimal)
+                            ..(hal::image::Access::SHADER_READ, hal::image::Layout::ShaderReadOnlyOptimal),
+                        target: image,
+                        families: Some(acquire.families.clone()),
+                        range,
+                    };
+                    acquire.cmd_buffer.pipeline_barrier(
+                        hal::pso::PipelineStage::TOP_OF_PIPE..hal::pso::PipelineStage::FRAGMENT_SHADER,
+                        hal::memory::Dependencies::empty(),
+                        &[acquire_barrier],
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // 创建大小为size的暂存缓冲, 用write写入数据, 返回它在staging中的下标
+    fn stage<T: Copy, F: FnOnce(&mut [T])>(
+        &mut self,
+        device: &<Back as hal::Backend>::Device,
+        allocator: &mut Allocator,
+        size: u64,
+        write: F,
+    ) -> Result<usize, AppError>
+    {
+        let mut buffer = unsafe {
+            device.create_buffer(size, hal::buffer::Usage::TRANSFER_SRC)
+        }.stage("create staging buffer")?;
+        let memory = match unsafe {
+            allocator.bind_buffer(
+                device,
+                &mut buffer,
+                hal::memory::Properties::CPU_VISIBLE | hal::memory::Properties::COHERENT,
+                hal::memory::Properties::empty(),
+                Strategy::General,
+            )
+        }.stage("allocate staging memory") {
+            Ok(memory) => memory,
+            Err(err) => {
+                unsafe {
+                    device.destroy_buffer(buffer);
+                }
+                return Err(err);
+            }
+        };
+        // 先放入staging, 映射失败时由cancel销毁
+        self.staging.push((buffer, memory));
+        let memory = &self.staging[self.staging.len() - 1].1;
+        unsafe {
+            let mut mapping = device
+                .acquire_mapping_writer::<T>(allocator.memory(memory), memory.range())
+                .stage("map staging memory")?;
+            write(&mut mapping[..]);
+            device.release_mapping_writer(mapping).stage("unmap staging memory")?;
+        }
+        Ok(self.staging.len() - 1)
+    }
+
+    // 结束记录并提交, 复制到缓冲区的数据之后可以在图形队列上用于顶点输入
+    // 创建栅栏或者信号量失败时取消上传
+    pub fn submit(
+        mut self,
+        device: &<Back as hal::Backend>::Device,
+        allocator: &mut Allocator,
+        queues: &mut Queues,
+    ) -> Result<PendingUpload, AppError>
+    {
+        let fence = match device.create_fence(false).stage("create upload fence") {
+            Ok(fence) => fence,
+            Err(err) => {
+                unsafe {
+                    self.cancel(device, allocator);
+                }
+                return Err(err);
+            }
+        };
+        // 只有转移所有权时才需要信号量
+        let semaphore = if self.acquire.is_some() {
+            match device.create_semaphore().stage("create upload semaphore") {
+                Ok(semaphore) => Some(semaphore),
+                Err(err) => {
+                    unsafe {
+                        device.destroy_fence(fence);
+                        self.cancel(device, allocator);
+                    }
+                    return Err(err);
+                }
+            }
+        } else {
+            None
+        };
+        let acquire_pool = match (self.acquire, &semaphore) {
+            (Some(mut acquire), Some(semaphore)) => unsafe {
+                self.cmd_buffer.finish();
+                acquire.cmd_buffer.finish();
+                queues.upload_queue().submit(
+                    hal::queue::Submission {
+                        command_buffers: Some(&self.cmd_buffer),
+                        wait_semaphores: None::<(&<Back as hal::Backend>::Semaphore, hal::pso::PipelineStage)>,
+                        signal_semaphores: Some(semaphore),
+                    },
+                    None,
+                );
+                // 图形队列等待传输队列完成后再获取所有权
+                queues.graphics.queues[0].submit(
+                    hal::queue::Submission {
+                        command_buffers: Some(&acquire.cmd_buffer),
+                        wait_semaphores: Some((semaphore, hal::pso::PipelineStage::TOP_OF_PIPE)),
+                        signal_semaphores: None::<&<Back as hal::Backend>::Semaphore>,
+                    },
+                    Some(&fence),
+                );
+                Some(acquire.cmd_pool)
+            },
+            _ => unsafe {
+                self.cmd_buffer.pipeline_barrier(
+                    hal::pso::PipelineStage::TRANSFER..hal::pso::PipelineStage::VERTEX_INPUT,
+                    hal::memory::Dependencies::empty(),
+                    &[hal::memory::Barrier::AllBuffers(
+                        hal::buffer::Access::TRANSFER_WRITE
+                            ..hal::buffer::Access::VERTEX_BUFFER_READ | hal::buffer::Access::INDEX_BUFFER_READ,
+                    )],
+                );
+                self.cmd_buffer.finish();
+                queues.upload_queue().submit_nosemaphores(Some(&self.cmd_buffer), Some(&fence));
+                None
+            },
+        };
+        Ok(PendingUpload {
+            fence,
+            semaphore,
+            cmd_pool: self.cmd_pool,
+            acquire_pool,
+            staging: self.staging,
+        })
+    }
+
+    // 放弃还没有提交的命令, 销毁命令池和已经创建的暂存缓冲
+    // 记录命令的过程中出错时使用, 已经记录的目标资源由调用者销毁
+    pub unsafe fn cancel(self, device: &<Back as hal::Backend>::Device, allocator: &mut Allocator) {
+        device.destroy_command_pool(self.cmd_pool.into_raw());
+        if let Some(acquire) = self.acquire {
+            device.destroy_command_pool(acquire.cmd_pool.into_raw());
+        }
+        for (buffer, memory) in self.staging {
+            device.destroy_buffer(buffer);
+            allocator.free(device, memory);
+        }
+    }
+}
+
+impl PendingUpload {
+    // 等待上传完成, 然后回收暂存缓冲
+    pub fn wait(
+        self,
+        device: &<Back as hal::Backend>::Device,
+        allocator: &mut Allocator,
+    ) -> Result<(), AppError>
+    {
+        unsafe {
+            device
+                .wait_for_fence(&self.fence, !0)
+                .stage("wait for upload")?;
+            device.destroy_fence(self.fence);
+            if let Some(semaphore) = self.semaphore {
+                device.destroy_semaphore(semaphore);
+            }
+            device.destroy_command_pool(self.cmd_pool.into_raw());
+            if let Some(acquire_pool) = self.acquire_pool {
+                device.destroy_command_pool(acquire_pool.into_raw());
+            }
+            for (buffer, memory) in self.staging {
+                device.destroy_buffer(buffer);
+                allocator.free(device, memory);
+            }
+        }
+        Ok(())
+    }
+}
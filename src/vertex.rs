@@ -0,0 +1,71 @@
+// 顶点格式: 根据顶点结构体的字段生成顶点缓冲和顶点属性的描述
+// 一般不需要手动实现VertexFormat, 使用#[derive(VertexFormat)]即可, 例如:
+//
+//     #[repr(C)]
+//     #[derive(Clone, Copy, VertexFormat)]
+//     struct Vertex {
+//         a_Pos: [f32; 3],  // location = 0, Rgb32Float
+//         a_Uv: [f32; 2],   // location = 1, Rg32Float
+//     }
+
+pub use hal::pso::{AttributeDesc, VertexBufferDesc};
+pub use gfx_test_derive::VertexFormat;
+
+pub trait VertexFormat: Copy {
+    // 每个字段对应一个顶点属性, location按照字段的声明顺序从0开始
+    fn attributes(binding: u32) -> Vec<AttributeDesc>;
+
+    // 顶点缓冲的描述, 步长是结构体的大小
+    fn buffer_desc(binding: u32) -> VertexBufferDesc {
+        VertexBufferDesc {
+            binding,
+            stride: std::mem::size_of::<Self>() as u32,
+            rate: 0,
+        }
+    }
+}
+
+// 可以作为顶点属性的字段类型
+pub trait AttributeFormat {
+    const FORMAT: hal::format::Format;
+}
+
+macro_rules! attribute_formats {
+    ($($ty:ty => $format:ident,)*) => {
+        $(
+            impl AttributeFormat for $ty {
+                const FORMAT: hal::format::Format = hal::format::Format::$format;
+            }
+        )*
+    };
+}
+
+attribute_formats! {
+    f32 => R32Float,
+    [f32; 2] => Rg32Float,
+    [f32; 3] => Rgb32Float,
+    [f32; 4] => Rgba32Float,
+    i32 => R32Int,
+    [i32; 2] => Rg32Int,
+    [i32; 3] => Rgb32Int,
+    [i32; 4] => Rgba32Int,
+    u32 => R32Uint,
+    [u32; 2] => Rg32Uint,
+    [u32; 3] => Rgb32Uint,
+    [u32; 4] => Rgba32Uint,
+    // 颜色通常用4个字节表示, 在着色器中读取为0到1之间的vec4
+    [u8; 4] => Rgba8Unorm,
+}
+
+// 派生宏生成的代码通过这个函数创建顶点属性, 字段类型不支持时无法编译
+#[doc(hidden)]
+pub fn attribute<T: AttributeFormat>(location: u32, binding: u32, offset: usize) -> AttributeDesc {
+    AttributeDesc {
+        location,
+        binding,
+        element: hal::pso::Element {
+            format: T::FORMAT,
+            offset: offset as u32,
+        },
+    }
+}
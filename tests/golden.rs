@@ -0,0 +1,87 @@
+// 离屏渲染场景, 并和tests/golden中的参考图片逐像素比较
+// 设置环境变量UPDATE_GOLDEN后运行, 会用当前的渲染结果覆盖参考图片
+#![cfg(not(feature = "empty"))]
+
+extern crate gfx_test;
+extern crate image;
+
+use gfx_test::headless::{Headless, Scene};
+
+use std::path::{Path, PathBuf};
+
+// 参考图片的尺寸
+const WIDTH: u32 = 256;
+const HEIGHT: u32 = 256;
+// 每个通道允许的最大误差, 不同驱动的插值和srgb转换会有细微差别
+const TOLERANCE: i16 = 3;
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden")
+}
+
+// 比较失败时, 把实际的渲染结果和差异图片写到target/golden中
+fn output_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("target").join("golden")
+}
+
+fn check(scene: Scene, name: &str) {
+    let actual = Headless::init(WIDTH, HEIGHT, scene, 1)
+        .and_then(|mut headless| headless.render())
+        .unwrap_or_else(|err| panic!("Cannot render {}: {}", name, err));
+    let reference_path = golden_dir().join(format!("{}.png", name));
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        actual.save(&reference_path).expect("Cannot save reference image");
+        return;
+    }
+
+    let reference = image::open(&reference_path)
+        .unwrap_or_else(|err| panic!("Cannot open {}: {}", reference_path.display(), err))
+        .to_rgba();
+    assert_eq!(actual.dimensions(), reference.dimensions());
+
+    // 差异图片中, 超出误差的像素标记为红色, 其余像素显示为变暗的实际结果
+    let mut diff = image::RgbaImage::new(WIDTH, HEIGHT);
+    let mut mismatched = 0;
+    for (x, y, pixel) in actual.enumerate_pixels() {
+        let expected = reference.get_pixel(x, y);
+        let out_of_tolerance = pixel.data
+            .iter()
+            .zip(expected.data.iter())
+            .any(|(&a, &b)| (a as i16 - b as i16).abs() > TOLERANCE);
+        if out_of_tolerance {
+            mismatched += 1;
+            diff.put_pixel(x, y, image::Rgba([255, 0, 0, 255]));
+        } else {
+            let [r, g, b, _] = pixel.data;
+            diff.put_pixel(x, y, image::Rgba([r / 4, g / 4, b / 4, 255]));
+        }
+    }
+
+    if mismatched > 0 {
+        let dir = output_dir();
+        std::fs::create_dir_all(&dir).expect("Cannot create output directory");
+        let actual_path = dir.join(format!("{}.actual.png", name));
+        let diff_path = dir.join(format!("{}.diff.png", name));
+        actual.save(&actual_path).expect("Cannot save actual image");
+        diff.save(&diff_path).expect("Cannot save diff image");
+        panic!(
+            "{}: {} pixels differ from {} by more than {}, see {}",
+            name,
+            mismatched,
+            reference_path.display(),
+            TOLERANCE,
+            diff_path.display(),
+        );
+    }
+}
+
+#[test]
+fn triangle() {
+    check(Scene::Triangle, "triangle");
+}
+
+#[test]
+fn quad() {
+    check(Scene::Quad, "quad");
+}